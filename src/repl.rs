@@ -1,17 +1,64 @@
+pub mod brackets;
+pub mod completion;
+mod edit;
+pub mod handler;
+pub mod highlight;
+pub mod hint;
+pub mod history;
+mod inputrc;
+pub mod keymap;
+pub mod keys;
+mod kill_ring;
+mod render;
+pub mod search;
+pub mod terminal;
+mod undo;
+mod utf8;
+pub mod validate;
+pub mod vi;
+
+use std::collections::HashMap;
 use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use term_manager::TermManager;
+use brackets::BracketHighlight;
+use completion::Completer;
+use handler::{FnHandler, LineHandler};
+use highlight::Highlighter;
+use hint::{Hinter, HistoryHinter};
+use history::History;
+use keymap::{Binding, Keymap};
+use keys::{Input, KeyCode, KeyEvent, KeyParser, Modifiers};
+use kill_ring::KillRing;
+use search::SearchState;
+use terminal::{StdTerminal, Terminal};
+use undo::UndoStack;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+use validate::Validation;
+use vi::{ViMode, ViState};
 
 pub type Result<T> = std::result::Result<T, Error>;
-pub type ProcessFunc = fn(String) -> Result<String>;
-pub type TerminatedLineFunc = fn(String) -> bool;
+
+/// How long to wait after Escape for the rest of a key sequence before
+/// treating it as a lone Escape key press.
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
 
 pub enum Error {
     InitFail(String),
     IoFlush(String),
     IoRead(String),
     IoWrite(String),
+    InvalidUtf8(String),
+    Terminal(String),
+    History(String),
+    Config(String),
     ProcessLine(String),
+    /// Ctrl-D was pressed on an empty line.
+    Eof,
+    /// Ctrl-C was pressed.
+    Interrupted,
 }
 
 impl Display for Error {
@@ -21,304 +68,970 @@ impl Display for Error {
             Error::IoFlush(s) => write!(f, "IO flush error: {}", s),
             Error::IoRead(s) => write!(f, "IO read error: {}", s),
             Error::IoWrite(s) => write!(f, "IO write error: {}", s),
+            Error::InvalidUtf8(s) => write!(f, "invalid UTF-8 input: {}", s),
+            Error::Terminal(s) => write!(f, "terminal error: {}", s),
+            Error::History(s) => write!(f, "history error: {}", s),
+            Error::Config(s) => write!(f, "config error: {}", s),
             Error::ProcessLine(s) => write!(f, "Process Line error: {}", s),
+            Error::Eof => write!(f, "end of input"),
+            Error::Interrupted => write!(f, "interrupted"),
         }
     }
 }
 
-enum ReplState {
-    Continue,
-    Break,
+/// How the `Repl` signals a key that had nothing to do.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BellStyle {
+    None,
+    Audible,
 }
 
-pub enum InputType {
-    Normal,
-    Escape,
-    EscapeSequence,
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EditMode {
+    Emacs,
+    Vi,
 }
 
-pub struct Repl {
-    tmanager: TermManager,
-    process_line: ProcessFunc,
-    line_is_finished: TerminatedLineFunc,
+pub struct Repl<T: Terminal = StdTerminal> {
+    terminal: T,
+    handler: Box<dyn LineHandler>,
     line: String,
-    lines: Vec<String>,
+    history: History,
     cursor_pos: usize,
     lines_pos: usize,
-    escape_buffer: Vec<u8>,
-    input_state: InputType,
+    /// Edited history entries by index, with the line being typed before
+    /// browsing at index `history.len()`. Kept until the line is submitted.
+    history_edits: HashMap<usize, String>,
+    key_parser: KeyParser,
+    keymap: Keymap,
+    /// Keys read so far of an unfinished chord.
+    pending_keys: Vec<KeyEvent>,
+    pending_byte: Option<u8>,
+    search: Option<SearchState>,
+    last_search_query: String,
+    completer: Option<Box<dyn Completer>>,
+    last_key_was_tab: bool,
+    kill_ring: KillRing,
+    undo_stack: UndoStack,
+    word_chars: Box<dyn Fn(char) -> bool>,
+    bell_style: BellStyle,
+    bracketed_paste: bool,
+    /// Typed characters replace the one under the cursor.
+    overwrite: bool,
+    vi: Option<ViState>,
+    vi_insert_indicator: String,
+    vi_normal_indicator: String,
     prompt: String,
+    continuation_prompt: String,
+    bracket_highlight: Option<BracketHighlight>,
+    highlighter: Option<Box<dyn Highlighter>>,
+    hinter: Option<Box<dyn Hinter>>,
+    /// Hint drawn after the line by the last refresh.
+    hint: Option<String>,
+    /// Terminal row of the cursor, counted from the first prompt row.
+    cursor_row: usize,
 }
 
-impl Repl {
+impl Repl<StdTerminal> {
     pub fn new(
         prompt: String,
-        process_line: ProcessFunc,
-        line_is_finished: TerminatedLineFunc,
+        process_line: impl FnMut(String) -> Result<String> + 'static,
+        line_is_finished: impl FnMut(String) -> bool + 'static,
     ) -> Result<Self> {
-        let tmanager = TermManager::new().or_else(|e| {
-            let msg = format!("failed to initialized Repl: {}", e);
-            Err(Error::InitFail(msg))
-        })?;
+        Repl::with_handler(prompt, FnHandler::new(process_line, line_is_finished))
+    }
+
+    /// Create a Repl that passes submitted lines to `handler`.
+    pub fn with_handler(prompt: String, handler: impl LineHandler + 'static) -> Result<Self> {
+        Ok(Repl::with_terminal(StdTerminal::new()?, prompt, handler))
+    }
+}
+
+impl<T: Terminal> Repl<T> {
+    /// Create a Repl that reads from and draws to the given terminal backend.
+    pub fn with_terminal(terminal: T, prompt: String, handler: impl LineHandler + 'static) -> Self {
         let line = String::new();
         let cursor_pos: usize = 0;
-        let lines: Vec<String> = Vec::new();
+        let history = History::default();
         let lines_pos: usize = 0;
-        let escape_buffer = Vec::new();
-        let input_state = InputType::Normal;
 
-        Ok(Repl {
-            tmanager,
-            process_line,
-            line_is_finished,
+        Repl {
+            terminal,
+            handler: Box::new(handler),
             line,
             cursor_pos,
-            lines,
+            history,
             lines_pos,
-            escape_buffer,
-            input_state,
+            history_edits: HashMap::new(),
+            key_parser: KeyParser::default(),
+            keymap: Keymap::emacs(),
+            pending_keys: Vec::new(),
+            pending_byte: None,
+            search: None,
+            last_search_query: String::new(),
+            completer: None,
+            last_key_was_tab: false,
+            kill_ring: KillRing::default(),
+            undo_stack: UndoStack::default(),
+            word_chars: Box::new(|c| c.is_alphanumeric() || c == '_'),
+            bell_style: BellStyle::Audible,
+            bracketed_paste: true,
+            overwrite: false,
+            vi: None,
+            vi_insert_indicator: String::from("(ins) "),
+            vi_normal_indicator: String::from("(cmd) "),
             prompt,
-        })
+            continuation_prompt: String::from("... "),
+            bracket_highlight: Some(BracketHighlight::default()),
+            highlighter: None,
+            hinter: Some(Box::new(HistoryHinter)),
+            hint: None,
+            cursor_row: 0,
+        }
+    }
+
+    pub fn terminal(&self) -> &T {
+        &self.terminal
+    }
+
+    pub fn terminal_mut(&mut self) -> &mut T {
+        &mut self.terminal
+    }
+
+    /// Current contents of the line being edited.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Byte offset of the cursor in the line being edited.
+    pub fn cursor_pos(&self) -> usize {
+        self.cursor_pos
+    }
+
+    /// Set the prompt shown before each continuation row of multi-line input.
+    pub fn set_continuation_prompt(&mut self, prompt: String) {
+        self.continuation_prompt = prompt;
+    }
+
+    /// Switch between Emacs and vi key bindings. Vi mode starts in insert
+    /// mode; Escape enters normal mode.
+    pub fn set_edit_mode(&mut self, mode: EditMode) {
+        self.vi = match mode {
+            EditMode::Emacs => None,
+            EditMode::Vi => Some(ViState::default()),
+        };
+    }
+
+    pub fn edit_mode(&self) -> EditMode {
+        match self.vi {
+            Some(_) => EditMode::Vi,
+            None => EditMode::Emacs,
+        }
+    }
+
+    /// Current vi mode, or `None` when using Emacs bindings.
+    pub fn vi_mode(&self) -> Option<ViMode> {
+        self.vi.as_ref().map(|vi| vi.mode)
+    }
+
+    /// Set the text shown before the prompt in vi insert and normal mode.
+    pub fn set_vi_mode_indicators(&mut self, insert: String, normal: String) {
+        self.vi_insert_indicator = insert;
+        self.vi_normal_indicator = normal;
+    }
+
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    pub fn keymap_mut(&mut self) -> &mut Keymap {
+        &mut self.keymap
+    }
+
+    /// Replace all key bindings.
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    /// Bind a key sequence to a command or to a closure that edits the line.
+    pub fn bind(&mut self, keys: &[KeyEvent], binding: impl Into<Binding>) {
+        self.keymap.bind(keys, binding);
+    }
+
+    /// Remove the binding for a key sequence.
+    pub fn unbind(&mut self, keys: &[KeyEvent]) {
+        self.keymap.unbind(keys);
+    }
+
+    pub fn set_bell_style(&mut self, style: BellStyle) {
+        self.bell_style = style;
+    }
+
+    /// Ask the terminal to mark pasted text so it is inserted as is instead
+    /// of being read as typed keys. On by default.
+    pub fn set_bracketed_paste(&mut self, enabled: bool) {
+        self.bracketed_paste = enabled;
+    }
+
+    /// Set which characters make up words for moving and killing by word.
+    /// Letters, digits and `_` are word characters by default.
+    pub fn set_word_chars(&mut self, is_word_char: impl Fn(char) -> bool + 'static) {
+        self.word_chars = Box::new(is_word_char);
+    }
+
+    /// Set the styles used to highlight matching and unmatched brackets.
+    /// Passing `None` turns bracket highlighting off.
+    pub fn set_bracket_highlight(&mut self, highlight: Option<BracketHighlight>) {
+        self.bracket_highlight = highlight;
+    }
+
+    /// Use `highlighter` to style the line as it is drawn.
+    pub fn set_highlighter(&mut self, highlighter: Option<Box<dyn Highlighter>>) {
+        self.highlighter = highlighter;
+    }
+
+    /// Use `hinter` to suggest text after the line. Hints from history are
+    /// shown by default; passing `None` turns hints off.
+    pub fn set_hinter(&mut self, hinter: Option<Box<dyn Hinter>>) {
+        self.hinter = hinter;
+    }
+
+    /// Use `completer` to complete the line when Tab is pressed.
+    pub fn set_completer(&mut self, completer: Option<Box<dyn Completer>>) {
+        self.completer = completer;
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Append the entries stored in `path` to the history.
+    pub fn load_history(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.history.load(path)?;
+        self.lines_pos = self.history.len();
+        self.history_edits.clear();
+        Ok(())
+    }
+
+    /// Write the history to `path`, replacing its contents.
+    pub fn save_history(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.history.save(path)
+    }
+
+    /// Append each submitted line to `path` as soon as it is accepted.
+    /// Passing `None` turns auto-append off. A failed write is reported below
+    /// the line and the session carries on. The file is never trimmed;
+    /// `set_max_history` only limits the entries kept in memory.
+    pub fn set_history_auto_append(&mut self, path: Option<PathBuf>) {
+        self.history.set_auto_append(path);
+    }
+
+    /// Leave empty and whitespace-only lines out of the history.
+    pub fn set_history_ignore_empty(&mut self, ignore: bool) {
+        self.history.set_ignore_empty(ignore);
     }
 
-    pub fn get_line(&mut self) -> Result<String> {
+    /// Leave a line out of the history when it repeats the newest entry.
+    pub fn set_history_ignore_dups(&mut self, ignore: bool) {
+        self.history.set_ignore_dups(ignore);
+    }
+
+    /// Remove older copies of a line from the history when it is submitted
+    /// again.
+    pub fn set_history_erase_dups(&mut self, erase: bool) {
+        self.history.set_erase_dups(erase);
+    }
+
+    /// Leave lines that start with a space out of the history.
+    pub fn set_history_ignore_space(&mut self, ignore: bool) {
+        self.history.set_ignore_space(ignore);
+    }
+
+    /// Only add submitted lines to the history when `filter` returns true.
+    pub fn set_history_filter(&mut self, filter: impl Fn(&str) -> bool + 'static) {
+        self.history.set_filter(filter);
+    }
+
+    /// Limit how many history entries are kept, dropping the oldest first.
+    pub fn set_max_history(&mut self, max_entries: usize) {
+        self.history.set_max_entries(max_entries);
+        self.lines_pos = self.lines_pos.min(self.history.len());
+        self.history_edits.clear();
+    }
+
+    /// Read lines and pass each finished one to the handler, until Ctrl-D
+    /// on an empty line returns `Error::Eof` or Ctrl-C returns
+    /// `Error::Interrupted`. The line being edited is left in place.
+    pub fn get_line(&mut self) -> Result<()> {
+        self.terminal.enable_raw_mode()?;
+        let result = if self.bracketed_paste {
+            self.terminal
+                .write(b"\x1b[?2004h")
+                .and_then(|_| self.edit_lines())
+        } else {
+            self.edit_lines()
+        };
+
+        // Always put the terminal back, even after an error, then report the
+        // first error.
+        let paste_off = if self.bracketed_paste {
+            self.terminal
+                .write(b"\x1b[?2004l")
+                .and_then(|_| self.terminal.flush())
+        } else {
+            Ok(())
+        };
+        let raw_off = self.terminal.disable_raw_mode();
+        result?;
+        paste_off?;
+        raw_off
+    }
+
+    fn edit_lines(&mut self) -> Result<()> {
+        self.refresh_line()?;
         loop {
-            let mut buf = [0u8; 1];
-            match self.tmanager.read(&mut buf) {
-                Ok(n) => n,
-                Err(e) => {
-                    eprintln!("Error reading from tmanager.stdin: {:?}", e);
-                    return Err(Error::IoFlush(format!("unable to flush stdout")));
-                }
+            // A byte that interrupted a UTF-8 sequence is replayed before reading more.
+            let c = match self.pending_byte.take() {
+                Some(c) => c,
+                None => self.terminal.read_byte()?,
             };
-            let c = buf[0];
-
-            match self.input_state {
-                InputType::Escape => {
-                    self.escape_buffer.push(c);
-                    match c {
-                        b'[' => {
-                            self.input_state = InputType::EscapeSequence;
-                        }
-                        _ => {
-                            self.input_state = InputType::Normal;
-                            self.escape_buffer.clear();
-                        }
-                    }
+
+            let key = match self.key_parser.push(c) {
+                Ok(Some(Input::Key(key))) => key,
+                Ok(Some(Input::Paste(text))) => {
+                    self.paste(&text)?;
+                    continue;
                 }
-                InputType::EscapeSequence => {
-                    self.escape_buffer.push(c);
-                    match self.handle_ansi_escape_sequence(c) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            eprintln!("error while reading char: {}", e);
-                            return Err(e);
-                        }
+                Ok(None) => {
+                    // Vi needs a lone Escape to leave insert mode, which can
+                    // only be told apart from a key sequence by timing.
+                    if self.vi.is_some()
+                        && self.key_parser.is_escape_pending()
+                        && !self.terminal.byte_ready(ESCAPE_TIMEOUT)?
+                        && let Some(key) = self.key_parser.flush_escape()
+                    {
+                        key
+                    } else {
+                        continue;
                     }
                 }
-                InputType::Normal => match self.handle_normal_input(c) {
-                    Ok(ReplState::Break) => break,
-                    Ok(ReplState::Continue) => continue,
-                    Err(e) => {
-                        eprintln!("error while reading char: {}", e);
-                        return Err(e);
-                    }
-                },
-            }
+                Err(e) => {
+                    self.pending_byte = e.unconsumed;
+                    return Err(Error::InvalidUtf8(e.to_string()));
+                }
+            };
+
+            self.kill_ring.start_key();
+            self.handle_key(key)?;
         }
+    }
 
-        Ok(self.line.clone())
-    }
-
-    fn handle_ansi_escape_sequence(&mut self, c: u8) -> Result<ReplState> {
-        match c {
-            // Get previous line from history.
-            b'A' => {
-                if self.lines.len() > 0 && self.lines_pos > 0 {
-                    self.line = self.lines[self.lines_pos - 1].clone();
-                    self.lines_pos -= 1;
-                    print!("\r{}{}\x1b[K", self.prompt, self.line);
-                    if let Err(e) = self.tmanager.flush() {
-                        eprintln!("{}", e);
-                        return Err(Error::IoFlush(format!("unable to flush stdout")));
-                    };
-                    self.cursor_pos = 0;
-                }
-                self.input_state = InputType::Normal;
-                self.escape_buffer.clear();
+    fn handle_key(&mut self, key: KeyEvent) -> Result<()> {
+        if self.search.is_some() {
+            return self.handle_search_key(key);
+        }
+        // A search is recorded as one undo step once it is finished.
+        self.undo_stack.start_key(&self.line, self.cursor_pos);
+        if key.code != KeyCode::Tab {
+            self.last_key_was_tab = false;
+        }
+
+        if let Some(vi) = &self.vi {
+            // Escape typed quickly before a key arrives as Alt.
+            if vi.mode == ViMode::Insert && key.modifiers.contains(Modifiers::ALT) {
+                self.enter_vi_normal_mode()?;
+                let key = KeyEvent::new(key.code, key.modifiers.without(Modifiers::ALT));
+                return self.handle_vi_key(key);
             }
-            // Get next line from history.
-            b'B' => {
-                if self.lines.len() > 0 && (self.lines_pos + 1) < self.lines.len() {
-                    self.lines_pos += 1;
-                    self.line = self.lines[self.lines_pos].clone();
-                    print!("\r{}{}\x1b[K", self.prompt, self.line);
-                    if let Err(e) = self.tmanager.flush() {
-                        eprintln!("{}", e);
-                        return Err(Error::IoFlush(format!("unable to flush stdout")));
-                    };
-                    self.cursor_pos = 0;
-                }
-                self.input_state = InputType::Normal;
-                self.escape_buffer.clear();
+            if vi.mode == ViMode::Normal {
+                return self.handle_vi_key(key);
             }
-            // Move cursor right.
-            b'C' => {
-                if self.cursor_pos < self.line.chars().count() {
-                    if let Err(e) = self.tmanager.write("\x1b[1C".as_bytes()) {
-                        eprintln!("{}", e);
-                        return Err(Error::IoWrite(format!("unable to write to stdout")));
-                    }
+        }
 
-                    if let Err(e) = self.tmanager.flush() {
-                        eprintln!("{}", e);
-                        return Err(Error::IoFlush(format!("unable to flush stdout")));
-                    }
+        self.handle_mapped_key(key)
+    }
 
-                    self.cursor_pos += 1;
-                }
-                self.input_state = InputType::Normal;
-                self.escape_buffer.clear();
+    /// Submit the line to the handler, or start a new row while the handler
+    /// says the line is unfinished.
+    fn accept_line(&mut self) -> Result<()> {
+        match self.handler.validate(&self.line) {
+            // Keep collecting rows until the line is finished.
+            Validation::Incomplete => {
+                self.line.insert(self.cursor_pos, '\n');
+                self.cursor_pos += 1;
+                return self.refresh_line();
             }
-            // Move cursor left.
-            b'D' => {
-                if self.cursor_pos > 0 {
-                    if let Err(e) = self.tmanager.write("\x1b[1D".as_bytes()) {
-                        eprintln!("{}", e);
-                        return Err(Error::IoWrite(format!("unable to write to stdout")));
-                    }
-                    if let Err(e) = self.tmanager.flush() {
-                        eprintln!("{}", e);
-                        return Err(Error::IoFlush(format!("unable to flush stdout")));
-                    };
-                    self.cursor_pos -= 1;
-                }
-                self.input_state = InputType::Normal;
-                self.escape_buffer.clear();
+            // Show the problem and let the user fix the line.
+            Validation::Invalid(message) => {
+                self.move_below_input()?;
+                self.terminal.write(format!("{}\r\n", message).as_bytes())?;
+                return self.refresh_line();
             }
-            _ => {}
+            Validation::Complete => {}
         }
 
-        Ok(ReplState::Continue)
+        // Process line and print result, without the hint.
+        self.cursor_pos = self.line.len();
+        self.hint = None;
+        self.draw_line()?;
+        self.move_below_input()?;
+        let processed_line = self.handler.process(self.line.clone())?;
+        let output = format!("{}\r\n", processed_line);
+        self.terminal.write(output.as_bytes())?;
+
+        // Start the next line before touching the history file, so a failed
+        // write cannot leave the submitted line in the buffer.
+        let line = std::mem::take(&mut self.line);
+        self.cursor_pos = 0;
+        self.history_edits.clear();
+        self.undo_stack.clear();
+        if let Some(vi) = self.vi.as_mut() {
+            vi.mode = ViMode::Insert;
+        }
+        let pushed = self.history.push(line);
+        self.lines_pos = self.history.len();
+        if let Err(e) = pushed {
+            self.terminal.write(format!("{}\r\n", e).as_bytes())?;
+        }
+        self.refresh_line()?;
+        Ok(())
     }
 
-    fn handle_normal_input(&mut self, c: u8) -> Result<ReplState> {
-        match c {
-            // Escape character.
-            b'\x1b' => {
-                self.input_state = InputType::Escape;
-                self.escape_buffer.clear();
-            }
-            b'q' | b'\x03' => return Ok(ReplState::Break),
-            // New line.
-            b'\n' | b'\r' => {
-                // Process line and print result if line is finished.
-                if (self.line_is_finished)(self.line.clone()) {
-                    let processed_line = match (self.process_line)(self.line.clone()) {
-                        Ok(s) => s,
-                        Err(e) => {
-                            eprintln!("error: {}", e);
-                            return Err(e);
-                        }
-                    };
-                    println!("\r\n{}", processed_line);
-                }
-                self.lines.push(self.line.clone());
-                self.lines_pos += 1;
-                self.line.clear();
-                self.cursor_pos = 0;
-                print!("{}", self.prompt);
-                if let Err(e) = self.tmanager.flush() {
-                    eprintln!("{}", e);
-                    return Err(Error::IoFlush(format!("unable to flush stdout")));
-                };
+    /// Route a decoded character to the search query, a vi command or the line.
+    fn input_char(&mut self, ch: char) -> Result<()> {
+        if self.search.is_some() {
+            self.search_push_char(ch)
+        } else if self.vi_normal_mode() {
+            self.vi_key(ch)
+        } else {
+            if let Some(vi) = self.vi.as_mut() {
+                vi.record_insert(ch);
             }
-            // Backspace.
-            b'\x08' | b'\x7f' => {
-                if self.cursor_pos > 0 {
-                    let mut byte_idx_to_remove = 0;
-                    let mut current_char_count = 0;
-                    for (idx, _) in self.line.char_indices() {
-                        if current_char_count == self.cursor_pos - 1 {
-                            byte_idx_to_remove = idx;
-                            break;
-                        }
-                        current_char_count += 1;
-                    }
-                    self.line.remove(byte_idx_to_remove);
+            self.insert_char(ch)
+        }
+    }
 
-                    self.cursor_pos -= 1;
+    /// Insert a decoded character at the cursor and redraw the line.
+    ///
+    /// Combining marks and joiners attach to the grapheme before the cursor,
+    /// so the cursor always moves past the whole cluster they extend. In
+    /// overwrite mode other characters replace the grapheme under the cursor.
+    fn insert_char(&mut self, ch: char) -> Result<()> {
+        if ch == '\t' || (ch.is_control() && !ch.is_whitespace()) {
+            return Ok(());
+        }
 
-                    if let Err(e) = self.tmanager.write("\x1b[1D".as_bytes()) {
-                        eprintln!("{}", e);
-                        return Err(Error::IoWrite(format!("unable to write to stdout")));
-                    }
-                    let clear_line_cmd = format!("{}\x1b[K", &self.line[byte_idx_to_remove..]);
-                    if let Err(e) = self.tmanager.write(clear_line_cmd.as_bytes()) {
-                        eprintln!("{}", e);
-                        return Err(Error::IoWrite(format!("unable to write to stdout")));
-                    }
-                    let chars_after_cursor = self.line.chars().skip(self.cursor_pos).count();
-                    if chars_after_cursor > 0 {
-                        let move_cursor_left = format!("\x1b[{}D", chars_after_cursor);
-                        if let Err(e) = self.tmanager.write(move_cursor_left.as_bytes()) {
-                            eprintln!("{}", e);
-                            return Err(Error::IoWrite(format!("unable to write to stdout")));
-                        }
-                    }
-                    if let Err(e) = self.tmanager.flush() {
-                        eprintln!("{}", e);
-                        return Err(Error::IoFlush(format!("unable to flush stdout")));
-                    };
-                }
+        self.undo_stack.note_insert();
+        let cluster_start = self.prev_grapheme_boundary().unwrap_or(0);
+        if self.overwrite
+            && let Some(next) = self.next_grapheme_boundary()
+            && &self.line[self.cursor_pos..next] != "\n"
+            && !self.extends_cluster(cluster_start, ch)
+        {
+            self.line.replace_range(self.cursor_pos..next, "");
+        }
+        self.line.insert(self.cursor_pos, ch);
+        self.cursor_pos += ch.len_utf8();
+        self.cursor_pos = self.line[cluster_start..]
+            .grapheme_indices(true)
+            .map(|(idx, g)| cluster_start + idx + g.len())
+            .find(|&end| end >= self.cursor_pos)
+            .unwrap_or(self.line.len());
+
+        self.refresh_line()
+    }
+
+    /// Ring the bell, unless it is turned off.
+    fn ding(&mut self) -> Result<()> {
+        if self.bell_style == BellStyle::Audible {
+            self.terminal.write(b"\x07")?;
+            self.terminal.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Insert pasted text at the cursor as a single edit. Tabs and line breaks
+    /// are kept; other control characters are dropped. While searching the
+    /// text extends the query instead.
+    fn paste(&mut self, text: &str) -> Result<()> {
+        if self.search.is_some() {
+            for ch in text.chars().filter(|c| !c.is_control()) {
+                self.search_push_char(ch)?;
             }
-            // Letter, number, symbol.
-            _ => {
-                if let Some(char_byte) = str::from_utf8(&[c]).ok().and_then(|s| s.chars().next()) {
-                    if char_byte.is_ascii_graphic()
-                        || (char_byte.is_whitespace() && char_byte != '\t')
-                    {
-                        if self.cursor_pos == self.line.chars().count() {
-                            print!("{}", char_byte);
-                            self.line.push(char_byte);
-                        } else {
-                            let mut byte_idx = 0;
-                            for (idx, _) in self.line.char_indices().take(self.cursor_pos) {
-                                byte_idx = idx;
-                            }
-                            self.line.insert(byte_idx, char_byte);
-                            let move_cursor_left = format!("\x1b[{}D", self.cursor_pos);
-                            if let Err(e) = self.tmanager.write(move_cursor_left.as_bytes()) {
-                                eprintln!("{}", e);
-                                return Err(Error::IoWrite(format!("unable to write to stdout")));
-                            }
-                            let clear_line_cmd = format!("{}\x1b[K", self.line);
-                            if let Err(e) = self.tmanager.write(clear_line_cmd.as_bytes()) {
-                                eprintln!("{}", e);
-                                return Err(Error::IoWrite(format!("unable to write to stdout")));
-                            }
-                            let chars_after_new_cursor =
-                                self.line.chars().skip(self.cursor_pos + 1).count();
-                            if chars_after_new_cursor > 0 {
-                                let move_cursor_left = format!("\x1b[{}D", chars_after_new_cursor);
-                                if let Err(e) = self.tmanager.write(move_cursor_left.as_bytes()) {
-                                    eprintln!("{}", e);
-                                    return Err(Error::IoWrite(format!(
-                                        "unable to write to stdout"
-                                    )));
-                                }
-                            }
-                        }
-                        self.cursor_pos += 1;
-                        if let Err(e) = self.tmanager.flush() {
-                            eprintln!("{}", e);
-                            return Err(Error::IoFlush(format!("unable to flush stdout")));
-                        };
-                    }
-                }
+            return Ok(());
+        }
+        self.kill_ring.start_key();
+        self.undo_stack.start_key(&self.line, self.cursor_pos);
+        let text: String = text
+            .chars()
+            .filter(|&c| c == '\n' || c == '\t' || !c.is_control())
+            .collect();
+        self.line.insert_str(self.cursor_pos, &text);
+        self.cursor_pos += text.len();
+        self.refresh_line()
+    }
+
+    /// True if `ch` joins the grapheme that starts at `cluster_start` and
+    /// ends at the cursor, as combining marks do.
+    fn extends_cluster(&self, cluster_start: usize, ch: char) -> bool {
+        if cluster_start == self.cursor_pos {
+            return false;
+        }
+        let mut cluster = self.line[cluster_start..self.cursor_pos].to_string();
+        cluster.push(ch);
+        cluster.graphemes(true).count() == 1
+    }
+
+    /// Move up a row, or to the previous history entry from the first row.
+    fn move_up(&mut self) -> Result<()> {
+        if self.row_start(self.cursor_pos) > 0 {
+            self.move_to_row(false)?;
+        } else if self.lines_pos > 0 {
+            self.select_history_entry(self.lines_pos - 1);
+            self.cursor_pos = self.line.len();
+            self.refresh_line()?;
+        }
+        Ok(())
+    }
+
+    /// Move down a row, or to the next history entry from the last row.
+    /// Moving past the newest entry returns to the line typed before
+    /// browsing.
+    fn move_down(&mut self) -> Result<()> {
+        if self.row_end(self.cursor_pos) < self.line.len() {
+            self.move_to_row(true)?;
+        } else if self.lines_pos < self.history.len() {
+            self.select_history_entry(self.lines_pos + 1);
+            self.cursor_pos = self.line.len();
+            self.refresh_line()?;
+        }
+        Ok(())
+    }
+
+    /// History entry `index` as it should be shown: with any edits made to
+    /// it, or the line typed before browsing at `history.len()`.
+    fn history_line(&self, index: usize) -> &str {
+        match self.history_edits.get(&index) {
+            Some(edited) => edited,
+            None => self.history.get(index).unwrap_or(""),
+        }
+    }
+
+    /// Keep `line` as the edited version of history entry `index`.
+    fn save_history_edit(&mut self, index: usize, line: String) {
+        if self.history.get(index) == Some(line.as_str()) {
+            self.history_edits.remove(&index);
+        } else {
+            self.history_edits.insert(index, line);
+        }
+    }
+
+    /// Replace the line with history entry `index`, keeping the edits made
+    /// to the entry being left. The cursor is left for the caller to place.
+    fn select_history_entry(&mut self, index: usize) {
+        let line = std::mem::take(&mut self.line);
+        self.save_history_edit(self.lines_pos, line);
+        self.lines_pos = index;
+        self.line = self.history_line(index).to_string();
+        self.cursor_pos = self.cursor_pos.min(self.line.len());
+    }
+
+    /// Byte offset of the start of the row containing `pos`.
+    fn row_start(&self, pos: usize) -> usize {
+        self.line[..pos].rfind('\n').map_or(0, |idx| idx + 1)
+    }
+
+    /// Byte offset of the end of the row containing `pos`.
+    fn row_end(&self, pos: usize) -> usize {
+        self.line[pos..]
+            .find('\n')
+            .map_or(self.line.len(), |idx| pos + idx)
+    }
+
+    /// Move the cursor to the row above or below, keeping its display column
+    /// where the target row is long enough.
+    fn move_to_row(&mut self, down: bool) -> Result<()> {
+        let start = self.row_start(self.cursor_pos);
+        let column = self.line[start..self.cursor_pos].width();
+        let target_start = if down {
+            self.row_end(self.cursor_pos) + 1
+        } else {
+            self.row_start(start - 1)
+        };
+        let target_end = self.row_end(target_start);
+
+        let mut width = 0;
+        self.cursor_pos = target_end;
+        for (idx, grapheme) in self.line[target_start..target_end].grapheme_indices(true) {
+            if width + grapheme.width() > column {
+                self.cursor_pos = target_start + idx;
+                break;
             }
+            width += grapheme.width();
+        }
+        self.refresh_line()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::terminal::MockTerminal;
+    use super::*;
+
+    /// Handler that prints each line unchanged.
+    pub(super) struct Echo;
+
+    impl LineHandler for Echo {
+        fn process(&mut self, line: String) -> Result<String> {
+            Ok(line)
+        }
+    }
+
+    pub(super) fn repl() -> Repl<MockTerminal> {
+        Repl::with_terminal(MockTerminal::new(""), String::from("> "), Echo)
+    }
+
+    /// A Repl whose history holds `entries`, oldest first.
+    pub(super) fn repl_with_history(entries: &[&str]) -> Repl<MockTerminal> {
+        let mut repl = repl();
+        for entry in entries {
+            assert!(repl.history.push(entry.to_string()).is_ok());
+        }
+        repl.lines_pos = repl.history.len();
+        repl
+    }
+
+    /// Type `input` into `repl`, then Ctrl-C to stop reading.
+    pub(super) fn type_keys(repl: &mut Repl<MockTerminal>, input: &str) {
+        repl.terminal_mut().push_input(input);
+        repl.terminal_mut().push_input("\x03");
+        assert!(matches!(repl.get_line(), Err(Error::Interrupted)));
+    }
+
+    /// Type `input` into a new Repl and return it.
+    pub(super) fn run(input: &str) -> Repl<MockTerminal> {
+        let mut repl = repl();
+        type_keys(&mut repl, input);
+        repl
+    }
+
+    #[test]
+    fn typed_characters_are_inserted() {
+        let repl = run("héllo");
+        assert_eq!(repl.line(), "héllo");
+        assert_eq!(repl.cursor_pos(), "héllo".len());
+    }
+
+    #[test]
+    fn get_line_leaves_raw_mode_and_bracketed_paste() {
+        let repl = run("a");
+        assert!(!repl.terminal().is_raw_mode());
+        let output = repl.terminal().output_str();
+        assert!(output.starts_with("\x1b[?2004h"));
+        assert!(output.ends_with("\x1b[?2004l"));
+        assert!(output.contains("> a"));
+    }
+
+    #[test]
+    fn get_line_leaves_raw_mode_after_write_errors() {
+        let mut repl = repl();
+        repl.terminal_mut().set_fail_writes(true);
+        repl.terminal_mut().push_input("a");
+        assert!(matches!(repl.get_line(), Err(Error::IoWrite(_))));
+        assert!(!repl.terminal().is_raw_mode());
+    }
+
+    #[test]
+    fn arrows_move_by_grapheme() {
+        let repl = run("ae\u{301}\x1b[D");
+        assert_eq!(repl.cursor_pos(), 1);
+        let repl = run("ae\u{301}\x1b[D\x1b[D\x1b[C");
+        assert_eq!(repl.cursor_pos(), 1);
+    }
+
+    #[test]
+    fn cursor_moves_by_display_columns() {
+        let mut repl = run("漢字");
+        repl.terminal_mut().clear_output();
+        type_keys(&mut repl, "\x1b[D");
+        assert_eq!(repl.cursor_pos(), "漢".len());
+        assert!(repl.terminal().output_str().contains("\r\x1b[4C"));
+    }
+
+    #[test]
+    fn enter_submits_the_line() {
+        let repl = run("ls\r");
+        assert_eq!(repl.line(), "");
+        assert_eq!(repl.history().entries(), ["ls"]);
+        assert!(repl.terminal().output_str().contains("\r\nls\r\n"));
+    }
+
+    #[test]
+    fn failed_auto_append_is_reported_and_the_line_is_reset() {
+        let mut repl = repl();
+        repl.set_history_auto_append(Some(PathBuf::from("/nonexistent-dir/h")));
+        type_keys(&mut repl, "ls\r");
+        assert_eq!(repl.line(), "");
+        assert_eq!(repl.cursor_pos(), 0);
+        assert_eq!(repl.history().entries(), ["ls"]);
+        let output = repl.terminal().output_str();
+        assert!(output.contains("history error: unable to open /nonexistent-dir/h"));
+
+        type_keys(&mut repl, "\x1b[A");
+        assert_eq!(repl.line(), "ls");
+    }
+
+    /// Handler that rejects unbalanced delimiters.
+    struct Delimited(validate::DelimiterValidator);
+
+    impl LineHandler for Delimited {
+        fn process(&mut self, line: String) -> Result<String> {
+            Ok(line)
+        }
+
+        fn validate(&mut self, line: &str) -> Validation {
+            self.0.validate(line)
         }
+    }
+
+    #[test]
+    fn validation_controls_submission() {
+        let handler = Delimited(validate::DelimiterValidator::new());
+        let mut repl = Repl::with_terminal(MockTerminal::new(""), String::from("> "), handler);
+        type_keys(&mut repl, "(]\r");
+        assert_eq!(repl.line(), "(]");
+        assert!(repl.history().is_empty());
+        assert!(
+            repl.terminal()
+                .output_str()
+                .contains("expected `)` but found `]`\r\n")
+        );
+
+        type_keys(&mut repl, "\x7f\r");
+        assert_eq!(repl.line(), "(\n");
+        type_keys(&mut repl, ")\r");
+        assert_eq!(repl.history().entries(), ["(\n)"]);
+    }
+
+    #[test]
+    fn up_and_down_walk_history() {
+        let mut repl = repl_with_history(&["one", "two"]);
+        type_keys(&mut repl, "\x1b[A\x1b[A");
+        assert_eq!(repl.line(), "one");
+        type_keys(&mut repl, "\x1b[B");
+        assert_eq!(repl.line(), "two");
+    }
+
+    #[test]
+    fn reverse_search_finds_history() {
+        let mut repl = repl_with_history(&["git status", "ls", "git push"]);
+        type_keys(&mut repl, "\x12git\x12\x1b[C");
+        assert_eq!(repl.line(), "git status");
+    }
+
+    /// A Repl that keeps reading rows while the line ends with a backslash.
+    fn multi_line_repl() -> Repl<MockTerminal> {
+        let handler = FnHandler::new(Ok, |line: String| !line.ends_with('\\'));
+        Repl::with_terminal(MockTerminal::new(""), String::from("> "), handler)
+    }
+
+    #[test]
+    fn unfinished_line_draws_continuation_prompt() {
+        let mut repl = multi_line_repl();
+        type_keys(&mut repl, "a\\\rb");
+        assert_eq!(repl.line(), "a\\\nb");
+        assert!(repl.terminal().output_str().contains("> a\\\r\n... b"));
+
+        let mut repl = multi_line_repl();
+        repl.set_continuation_prompt(String::from("| "));
+        type_keys(&mut repl, "a\\\rb");
+        assert!(repl.terminal().output_str().contains("> a\\\r\n| b"));
+    }
+
+    #[test]
+    fn up_and_down_move_between_rows() {
+        let mut repl = multi_line_repl();
+        type_keys(&mut repl, "abc\\\rd\x1b[A");
+        assert_eq!(repl.cursor_pos(), 1);
+        type_keys(&mut repl, "\x1b[B");
+        assert_eq!(repl.cursor_pos(), repl.line().len());
+    }
+
+    #[test]
+    fn backspace_and_delete() {
+        assert_eq!(run("abc\x7f").line(), "ab");
+        assert_eq!(run("abc\x01\x04").line(), "bc");
+    }
+
+    #[test]
+    fn ctrl_d_on_empty_line_returns_eof() {
+        let mut repl = repl();
+        repl.terminal_mut().push_input("\x04");
+        assert!(matches!(repl.get_line(), Err(Error::Eof)));
+    }
+
+    #[test]
+    fn ctrl_c_returns_interrupted_and_keeps_the_line() {
+        let mut repl = repl();
+        repl.terminal_mut().push_input("ab\x03");
+        assert!(matches!(repl.get_line(), Err(Error::Interrupted)));
+        assert_eq!(repl.line(), "ab");
+    }
+
+    #[test]
+    fn line_start_and_end() {
+        assert_eq!(run("abc\x01X\x05Y").line(), "XabcY");
+    }
+
+    #[test]
+    fn ctrl_b_and_ctrl_f_move_by_grapheme() {
+        assert_eq!(run("ab\x02\x02\x06").cursor_pos(), 1);
+        assert_eq!(run("ae\u{301}\x02").cursor_pos(), 1);
+    }
+
+    #[test]
+    fn kills_and_yank() {
+        assert_eq!(run("foo bar\x17").line(), "foo ");
+        assert_eq!(run("foo bar\x02\x02\x02\x0b").line(), "foo ");
+        assert_eq!(run("foo bar\x15").line(), "");
+        assert_eq!(run("foo bar\x02\x02\x02\x0b\x01\x19").line(), "barfoo ");
+    }
+
+    #[test]
+    fn transpose_chars() {
+        assert_eq!(run("ab\x14").line(), "ba");
+        assert_eq!(run("abc\x02\x14").line(), "acb");
+    }
+
+    #[test]
+    fn consecutive_kills_are_joined() {
+        assert_eq!(run("one two\x17\x17\x19").line(), "one two");
+    }
+
+    #[test]
+    fn yank_pop_cycles_the_kill_ring() {
+        assert_eq!(run("a\x15b\x15\x19\x1by").line(), "a");
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        assert_eq!(run("abc def\x17\x1f").line(), "abc def");
+        assert_eq!(run("abc def\x17\x1a").line(), "abc def");
+        assert_eq!(run("abc def\x17\x1f\x1b/").line(), "abc ");
+        assert_eq!(run("abc\x18\x15").line(), "");
+    }
+
+    #[test]
+    fn word_motions() {
+        assert_eq!(run("foo bar\x1bb").cursor_pos(), 4);
+        assert_eq!(run("foo bar\x1b[1;5D").cursor_pos(), 4);
+        assert_eq!(run("foo bar\x01\x1bf").cursor_pos(), 3);
+        assert_eq!(run("foo bar\x01\x1b[1;3C").cursor_pos(), 3);
+    }
+
+    #[test]
+    fn kill_words() {
+        assert_eq!(run("foo bar\x01\x1bd").line(), " bar");
+        assert_eq!(run("foo bar\x1b\x7f").line(), "foo ");
+        assert_eq!(run("foo bar\x1bb\x0b").line(), "foo ");
+    }
+
+    #[test]
+    fn home_end_and_delete_keys() {
+        assert_eq!(run("abc\x1b[HX\x1b[FY").line(), "XabcY");
+        assert_eq!(run("abc\x1b[1~X\x1b[4~Y").line(), "XabcY");
+        assert_eq!(run("abc\x1b[D\x1b[D\x1b[3~").line(), "ac");
+    }
+
+    #[test]
+    fn insert_toggles_overwrite() {
+        let repl = run("abc\x01\x1b[2~XY");
+        assert_eq!(repl.line(), "XYc");
+        assert_eq!(run("abc\x01\x1b[2~X\x1b[2~Y").line(), "XYbc");
+    }
+
+    #[test]
+    fn custom_bindings() {
+        let mut repl = repl();
+        repl.bind(
+            &[KeyEvent::ctrl('x'), KeyEvent::ctrl('e')],
+            |buffer: &mut keymap::LineBuffer| buffer.insert_str("echo "),
+        );
+        repl.unbind(&[KeyEvent::ctrl('a')]);
+        type_keys(&mut repl, "hi\x18\x05\x01");
+        assert_eq!(repl.line(), "hiecho ");
+    }
+
+    #[test]
+    fn unbound_chord_rings_the_bell() {
+        assert!(run("\x07").terminal().output_str().contains('\x07'));
+        let mut repl = repl();
+        repl.set_bell_style(BellStyle::None);
+        type_keys(&mut repl, "\x07");
+        assert!(!repl.terminal().output_str().contains('\x07'));
+    }
+
+    #[test]
+    fn bracketed_paste_is_inserted_as_text() {
+        let repl = run("\x1b[200~a\r\nb\tc\x1b[201~");
+        assert_eq!(repl.line(), "a\nb\tc");
+    }
+
+    #[test]
+    fn page_up_and_down_search_by_prefix() {
+        let mut repl = repl_with_history(&["git status", "ls", "git push", "cargo"]);
+        type_keys(&mut repl, "git\x1b[5~");
+        assert_eq!(repl.line(), "git push");
+        assert_eq!(repl.cursor_pos(), 3);
+        type_keys(&mut repl, "\x1b[5~");
+        assert_eq!(repl.line(), "git status");
+        assert_eq!(repl.cursor_pos(), 3);
+        type_keys(&mut repl, "\x1b[6~");
+        assert_eq!(repl.line(), "git push");
+        assert_eq!(repl.cursor_pos(), 3);
+    }
+
+    #[test]
+    fn prefix_search_without_a_match_rings_the_bell() {
+        let mut repl = repl_with_history(&["git status"]);
+        type_keys(&mut repl, "ls\x1b[5~");
+        assert_eq!(repl.line(), "ls");
+        assert!(repl.terminal().output_str().contains('\x07'));
+    }
+
+    #[test]
+    fn down_past_the_newest_entry_restores_the_draft() {
+        let mut repl = repl_with_history(&["one", "two"]);
+        type_keys(&mut repl, "dr\x1b[A");
+        assert_eq!(repl.line(), "two");
+        type_keys(&mut repl, "\x1b[B");
+        assert_eq!(repl.line(), "dr");
+    }
+
+    #[test]
+    fn edits_to_history_entries_are_kept_while_browsing() {
+        let mut repl = repl_with_history(&["one", "two"]);
+        type_keys(&mut repl, "\x1b[AX\x1b[A");
+        assert_eq!(repl.line(), "one");
+        type_keys(&mut repl, "\x1b[B");
+        assert_eq!(repl.line(), "twoX");
+        assert_eq!(repl.history().entries(), ["one", "two"]);
+    }
+
+    #[test]
+    fn history_edits_are_cleared_on_submit() {
+        let mut repl = repl_with_history(&["one", "two"]);
+        type_keys(&mut repl, "\x1b[AX\r");
+        assert_eq!(repl.history().entries(), ["one", "two", "twoX"]);
+        type_keys(&mut repl, "\x1b[A\x1b[A");
+        assert_eq!(repl.line(), "two");
+    }
 
-        Ok(ReplState::Continue)
+    #[test]
+    fn prefix_search_forward_returns_to_the_draft() {
+        let mut repl = repl_with_history(&["git status", "git push"]);
+        type_keys(&mut repl, "git\x1b[5~\x1b[6~");
+        assert_eq!(repl.line(), "git");
+        assert_eq!(repl.cursor_pos(), 3);
     }
 }
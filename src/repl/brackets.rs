@@ -0,0 +1,79 @@
+use super::validate::{Token, Tokens, opener_of};
+
+/// Styles used to highlight brackets around the cursor.
+///
+/// Each style is an escape sequence written before the bracket; the terminal
+/// attributes are reset right after it.
+pub struct BracketHighlight {
+    /// Style of the bracket matching the one at the cursor.
+    pub matched: String,
+    /// Style of closing brackets that have no opening partner.
+    pub unmatched: String,
+}
+
+impl Default for BracketHighlight {
+    fn default() -> Self {
+        BracketHighlight {
+            matched: String::from("\x1b[7m"),
+            unmatched: String::from("\x1b[31m"),
+        }
+    }
+}
+
+/// Byte offset of every bracket outside of quotes, paired with the offset of
+/// its partner. Unclosed openers and unexpected closers have no partner.
+pub(super) fn bracket_pairs(line: &str) -> Vec<(usize, Option<usize>)> {
+    let mut pairs = Vec::new();
+    let mut stack: Vec<(usize, char)> = Vec::new();
+
+    for token in Tokens::new(line) {
+        match token {
+            Token::Open(idx, c) => {
+                stack.push((idx, c));
+                pairs.push((idx, None));
+            }
+            Token::Close(idx, c) => match stack.last() {
+                Some(&(open_idx, open)) if open == opener_of(c) => {
+                    stack.pop();
+                    pairs.push((idx, Some(open_idx)));
+                    if let Some(pair) = pairs.iter_mut().find(|(i, _)| *i == open_idx) {
+                        pair.1 = Some(idx);
+                    }
+                }
+                _ => pairs.push((idx, None)),
+            },
+            Token::Word(_) | Token::UnclosedQuote => {}
+        }
+    }
+
+    pairs
+}
+
+pub(super) fn is_closer(c: char) -> bool {
+    matches!(c, ')' | ']' | '}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_nested_brackets() {
+        assert_eq!(
+            bracket_pairs("(a[b])"),
+            [(0, Some(5)), (2, Some(4)), (4, Some(2)), (5, Some(0))]
+        );
+    }
+
+    #[test]
+    fn unmatched_brackets_have_no_partner() {
+        assert_eq!(bracket_pairs("(]"), [(0, None), (1, None)]);
+        assert_eq!(bracket_pairs(")("), [(0, None), (1, None)]);
+    }
+
+    #[test]
+    fn quoted_brackets_are_skipped() {
+        assert_eq!(bracket_pairs("(\")\\\"\")"), [(0, Some(6)), (6, Some(0))]);
+        assert_eq!(bracket_pairs("'(' ("), [(4, None)]);
+    }
+}
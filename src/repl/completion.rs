@@ -0,0 +1,211 @@
+use std::ops::Range;
+
+use unicode_width::UnicodeWidthStr;
+
+use super::terminal::Terminal;
+use super::{Repl, Result};
+
+/// Candidates offered for the text at `span` in the line.
+pub struct Completion {
+    /// Byte range of the line replaced by the chosen candidate. A span that
+    /// is out of range or splits a character rings the bell instead.
+    pub span: Range<usize>,
+    pub candidates: Vec<String>,
+}
+
+/// Source of Tab completions for the `Repl`.
+pub trait Completer {
+    /// Return candidates for the line with the cursor at byte offset `pos`,
+    /// or `None` if there is nothing to complete.
+    fn complete(&mut self, line: &str, pos: usize) -> Option<Completion>;
+}
+
+impl<T: Terminal> Repl<T> {
+    /// Complete the text before the cursor. A unique candidate replaces the
+    /// span, several candidates insert their longest common prefix, and a
+    /// second Tab without progress lists them all.
+    pub(super) fn complete(&mut self) -> Result<()> {
+        let listing = self.last_key_was_tab;
+        self.last_key_was_tab = true;
+
+        let Some(completer) = self.completer.as_mut() else {
+            return Ok(());
+        };
+        let Some(completion) = completer.complete(&self.line, self.cursor_pos) else {
+            return self.ding();
+        };
+        let Completion {
+            span,
+            mut candidates,
+        } = completion;
+        let valid_span = span.start <= span.end
+            && self.line.is_char_boundary(span.start)
+            && self.line.is_char_boundary(span.end);
+        if candidates.is_empty() || !valid_span {
+            return self.ding();
+        }
+        candidates.sort();
+        candidates.dedup();
+
+        if candidates.len() == 1 {
+            self.replace_span(span, &candidates[0]);
+            return self.refresh_line();
+        }
+
+        let prefix = common_prefix(&candidates);
+        if prefix.len() > span.len() {
+            self.replace_span(span, prefix);
+            return self.refresh_line();
+        }
+
+        if listing {
+            self.list_candidates(&candidates)
+        } else {
+            self.ding()
+        }
+    }
+
+    fn replace_span(&mut self, span: Range<usize>, replacement: &str) {
+        self.line.replace_range(span.clone(), replacement);
+        self.cursor_pos = span.start + replacement.len();
+    }
+
+    /// Print candidates in columns below the line, then redraw the prompt.
+    fn list_candidates(&mut self, candidates: &[String]) -> Result<()> {
+        let columns = self.columns();
+        let cell_width = candidates.iter().map(|c| c.width()).max().unwrap_or(0) + 2;
+        let per_row = (columns / cell_width).max(1);
+
+        self.move_below_input()?;
+        let mut output = String::new();
+        for (i, candidate) in candidates.iter().enumerate() {
+            output.push_str(candidate);
+            if (i + 1) % per_row == 0 || i + 1 == candidates.len() {
+                output.push_str("\r\n");
+            } else {
+                let padding = cell_width - candidate.width();
+                output.push_str(&" ".repeat(padding));
+            }
+        }
+        self.terminal.write(output.as_bytes())?;
+        self.refresh_line()
+    }
+}
+
+/// Longest prefix shared by every candidate, cut at a char boundary.
+fn common_prefix(candidates: &[String]) -> &str {
+    let first = &candidates[0];
+    let mut len = first.len();
+    for candidate in &candidates[1..] {
+        len = first
+            .char_indices()
+            .zip(candidate.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(len.min(candidate.len()), |((idx, _), _)| idx.min(len));
+    }
+    &first[..len]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{repl, type_keys};
+    use super::*;
+
+    /// Completes the word before the cursor from a fixed list.
+    struct Words(&'static [&'static str]);
+
+    impl Completer for Words {
+        fn complete(&mut self, line: &str, pos: usize) -> Option<Completion> {
+            let start = line[..pos].rfind(' ').map_or(0, |idx| idx + 1);
+            let word = &line[start..pos];
+            let candidates = self
+                .0
+                .iter()
+                .filter(|w| w.starts_with(word))
+                .map(|w| w.to_string())
+                .collect();
+            Some(Completion {
+                span: start..pos,
+                candidates,
+            })
+        }
+    }
+
+    fn run_completion(words: &'static [&'static str], input: &str) -> String {
+        let mut repl = repl();
+        repl.set_completer(Some(Box::new(Words(words))));
+        type_keys(&mut repl, input);
+        repl.terminal().output_str()
+    }
+
+    #[test]
+    fn unique_candidate_replaces_the_word() {
+        let mut repl = repl();
+        repl.set_completer(Some(Box::new(Words(&["print", "exit"]))));
+        type_keys(&mut repl, "x p\t");
+        assert_eq!(repl.line(), "x print");
+    }
+
+    #[test]
+    fn common_prefix_is_inserted() {
+        let mut repl = repl();
+        repl.set_completer(Some(Box::new(Words(&["print", "println"]))));
+        type_keys(&mut repl, "p\t");
+        assert_eq!(repl.line(), "print");
+    }
+
+    #[test]
+    fn second_tab_lists_candidates() {
+        let output = run_completion(&["print", "println", "exit"], "print\t");
+        assert!(!output.contains("println  "));
+        let output = run_completion(&["print", "println", "exit"], "print\t\t");
+        assert!(output.contains("print    println\r\n"));
+    }
+
+    #[test]
+    fn no_candidates_rings_the_bell() {
+        assert!(run_completion(&["print"], "x\t").contains('\x07'));
+    }
+
+    /// Offers one candidate for a fixed byte range of the line.
+    struct FixedSpan(Range<usize>, &'static str);
+
+    impl Completer for FixedSpan {
+        fn complete(&mut self, _line: &str, _pos: usize) -> Option<Completion> {
+            Some(Completion {
+                span: self.0.clone(),
+                candidates: vec![String::from(self.1)],
+            })
+        }
+    }
+
+    #[test]
+    fn invalid_spans_ring_the_bell() {
+        for span in [1..2, 3..9] {
+            let mut repl = repl();
+            repl.set_completer(Some(Box::new(FixedSpan(span, "x"))));
+            type_keys(&mut repl, "é\t");
+            assert_eq!(repl.line(), "é");
+            assert!(repl.terminal().output_str().contains('\x07'));
+        }
+    }
+
+    #[test]
+    fn common_prefix_stops_at_char_boundary() {
+        let candidates = vec![String::from("héllo"), String::from("hèllo")];
+        assert_eq!(common_prefix(&candidates), "h");
+    }
+
+    #[test]
+    fn listing_falls_back_to_80_columns() {
+        let mut repl = repl();
+        repl.terminal_mut().set_size(0, 0);
+        repl.set_completer(Some(Box::new(Words(&["print", "println"]))));
+        type_keys(&mut repl, "print\t\t");
+        assert!(
+            repl.terminal()
+                .output_str()
+                .contains("print    println\r\n")
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::terminal::Terminal;
+use super::{Repl, Result};
+
+impl<T: Terminal> Repl<T> {
+    /// Move the cursor to the start of the current row.
+    pub(super) fn beginning_of_line(&mut self) -> Result<()> {
+        self.cursor_pos = self.row_start(self.cursor_pos);
+        self.refresh_line()
+    }
+
+    /// Move the cursor to the end of the current row.
+    pub(super) fn end_of_line(&mut self) -> Result<()> {
+        if self.cursor_pos == self.line.len() && self.hint.is_some() {
+            return self.accept_hint(false);
+        }
+        self.cursor_pos = self.row_end(self.cursor_pos);
+        self.refresh_line()
+    }
+
+    pub(super) fn backward_char(&mut self) -> Result<()> {
+        if let Some(prev) = self.prev_grapheme_boundary() {
+            self.cursor_pos = prev;
+            self.refresh_line()?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn forward_char(&mut self) -> Result<()> {
+        if self.cursor_pos == self.line.len() && self.hint.is_some() {
+            return self.accept_hint(false);
+        }
+        if let Some(next) = self.next_grapheme_boundary() {
+            self.cursor_pos = next;
+            self.refresh_line()?;
+        }
+        Ok(())
+    }
+
+    /// Move the cursor to the end of the next word.
+    pub(super) fn forward_word(&mut self) -> Result<()> {
+        if self.cursor_pos == self.line.len() && self.hint.is_some() {
+            return self.accept_hint(true);
+        }
+        self.cursor_pos = self.next_word_end();
+        self.refresh_line()
+    }
+
+    /// Append the hint to the line, or only up to the end of its first word
+    /// when `word` is set, and move the cursor to the end.
+    fn accept_hint(&mut self, word: bool) -> Result<()> {
+        let Some(hint) = self.hint.take() else {
+            return Ok(());
+        };
+        self.line.push_str(&hint);
+        if word {
+            let end = self.next_word_end();
+            self.line.truncate(end);
+        }
+        self.cursor_pos = self.line.len();
+        self.refresh_line()
+    }
+
+    /// Move the cursor to the start of the previous word.
+    pub(super) fn backward_word(&mut self) -> Result<()> {
+        self.cursor_pos = self.prev_word_start();
+        self.refresh_line()
+    }
+
+    /// Delete the grapheme before the cursor.
+    pub(super) fn backward_delete_char(&mut self) -> Result<()> {
+        if let Some(prev) = self.prev_grapheme_boundary() {
+            self.line.replace_range(prev..self.cursor_pos, "");
+            self.cursor_pos = prev;
+            self.refresh_line()?;
+        }
+        Ok(())
+    }
+
+    /// Delete the grapheme under the cursor.
+    pub(super) fn delete_char(&mut self) -> Result<()> {
+        if let Some(next) = self.next_grapheme_boundary() {
+            self.line.replace_range(self.cursor_pos..next, "");
+            self.refresh_line()?;
+        }
+        Ok(())
+    }
+
+    /// Kill from the cursor to the end of the row. At the end of a row the
+    /// line break is killed instead, joining it with the next row.
+    pub(super) fn kill_line(&mut self) -> Result<()> {
+        let end = self.row_end(self.cursor_pos);
+        if end > self.cursor_pos {
+            self.kill(self.cursor_pos..end)
+        } else if end < self.line.len() {
+            self.kill(end..end + 1)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Kill from the start of the row to the cursor.
+    pub(super) fn unix_line_discard(&mut self) -> Result<()> {
+        let start = self.row_start(self.cursor_pos);
+        self.kill(start..self.cursor_pos)
+    }
+
+    /// Kill from the cursor to the end of the next word.
+    pub(super) fn kill_word(&mut self) -> Result<()> {
+        let end = self.next_word_end();
+        self.kill(self.cursor_pos..end)
+    }
+
+    /// Kill from the start of the previous word to the cursor.
+    pub(super) fn backward_kill_word(&mut self) -> Result<()> {
+        let start = self.prev_word_start();
+        self.kill(start..self.cursor_pos)
+    }
+
+    /// Kill the whitespace-delimited word before the cursor.
+    pub(super) fn unix_word_rubout(&mut self) -> Result<()> {
+        let before = &self.line[..self.cursor_pos];
+        let word_end = before.trim_end_matches(char::is_whitespace).len();
+        let start = before[..word_end]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(0, |(idx, c)| idx + c.len_utf8());
+        self.kill(start..self.cursor_pos)
+    }
+
+    /// Swap the graphemes around the cursor and move past them. At the end of
+    /// a row the two graphemes before the cursor are swapped.
+    pub(super) fn transpose_chars(&mut self) -> Result<()> {
+        let original = self.cursor_pos;
+        if self.cursor_pos == self.row_end(self.cursor_pos)
+            && let Some(prev) = self.prev_grapheme_boundary()
+        {
+            self.cursor_pos = prev;
+        }
+        let (Some(prev), Some(next)) =
+            (self.prev_grapheme_boundary(), self.next_grapheme_boundary())
+        else {
+            self.cursor_pos = original;
+            return Ok(());
+        };
+        let left = &self.line[prev..self.cursor_pos];
+        let right = &self.line[self.cursor_pos..next];
+        if left == "\n" || right == "\n" {
+            self.cursor_pos = original;
+            return Ok(());
+        }
+        let swapped = format!("{}{}", right, left);
+        self.line.replace_range(prev..next, &swapped);
+        self.cursor_pos = next;
+        self.refresh_line()
+    }
+
+    /// Remove `range` from the line and add it to the kill ring.
+    fn kill(&mut self, range: Range<usize>) -> Result<()> {
+        if range.is_empty() {
+            return Ok(());
+        }
+        let backward = range.end == self.cursor_pos;
+        self.kill_ring.kill(&self.line[range.clone()], backward);
+        self.line.replace_range(range.clone(), "");
+        self.cursor_pos = range.start;
+        self.refresh_line()
+    }
+
+    /// Byte offset just past the word after the cursor, skipping any
+    /// non-word characters before it.
+    fn next_word_end(&self) -> usize {
+        let mut in_word = false;
+        for (idx, g) in self.line[self.cursor_pos..].grapheme_indices(true) {
+            if self.is_word(g) {
+                in_word = true;
+            } else if in_word {
+                return self.cursor_pos + idx;
+            }
+        }
+        self.line.len()
+    }
+
+    /// Byte offset of the start of the word before the cursor, skipping any
+    /// non-word characters after it.
+    fn prev_word_start(&self) -> usize {
+        let mut in_word = false;
+        for (idx, g) in self.line[..self.cursor_pos].grapheme_indices(true).rev() {
+            if self.is_word(g) {
+                in_word = true;
+            } else if in_word {
+                return idx + g.len();
+            }
+        }
+        0
+    }
+
+    fn is_word(&self, grapheme: &str) -> bool {
+        grapheme
+            .chars()
+            .next()
+            .is_some_and(|c| (self.word_chars)(c))
+    }
+
+    /// Byte index of the grapheme cluster before the cursor, if any.
+    pub(super) fn prev_grapheme_boundary(&self) -> Option<usize> {
+        self.line[..self.cursor_pos]
+            .grapheme_indices(true)
+            .next_back()
+            .map(|(idx, _)| idx)
+    }
+
+    /// Byte index just past the grapheme cluster under the cursor, if any.
+    pub(super) fn next_grapheme_boundary(&self) -> Option<usize> {
+        self.line[self.cursor_pos..]
+            .graphemes(true)
+            .next()
+            .map(|g| self.cursor_pos + g.len())
+    }
+}
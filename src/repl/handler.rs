@@ -0,0 +1,103 @@
+use super::Result;
+use super::validate::Validation;
+
+/// Evaluates lines submitted to the `Repl`.
+///
+/// Implementors can keep state, such as an interpreter environment, between
+/// lines.
+pub trait LineHandler {
+    /// Process a finished line and return the text to print.
+    fn process(&mut self, line: String) -> Result<String>;
+
+    /// Returns false while the line needs more input before it can be
+    /// processed. Every line is finished by default.
+    fn is_finished(&mut self, _line: &str) -> bool {
+        true
+    }
+
+    /// Check a line when Enter is pressed. `Incomplete` starts a new row and
+    /// `Invalid` shows its message below the line and keeps editing. By
+    /// default the line is complete once `is_finished` returns true.
+    fn validate(&mut self, line: &str) -> Validation {
+        if self.is_finished(line) {
+            Validation::Complete
+        } else {
+            Validation::Incomplete
+        }
+    }
+}
+
+/// `LineHandler` built from a pair of closures.
+pub struct FnHandler<P, F> {
+    process_line: P,
+    line_is_finished: F,
+}
+
+impl<P, F> FnHandler<P, F>
+where
+    P: FnMut(String) -> Result<String>,
+    F: FnMut(String) -> bool,
+{
+    pub fn new(process_line: P, line_is_finished: F) -> Self {
+        FnHandler {
+            process_line,
+            line_is_finished,
+        }
+    }
+}
+
+impl<P, F> LineHandler for FnHandler<P, F>
+where
+    P: FnMut(String) -> Result<String>,
+    F: FnMut(String) -> bool,
+{
+    fn process(&mut self, line: String) -> Result<String> {
+        (self.process_line)(line)
+    }
+
+    fn is_finished(&mut self, line: &str) -> bool {
+        (self.line_is_finished)(line.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Repl;
+    use super::super::terminal::MockTerminal;
+    use super::super::tests::type_keys;
+    use super::*;
+
+    /// Handler that numbers each line it processes.
+    struct Counter(usize);
+
+    impl LineHandler for Counter {
+        fn process(&mut self, line: String) -> Result<String> {
+            self.0 += 1;
+            Ok(format!("{}: {}", self.0, line))
+        }
+    }
+
+    #[test]
+    fn line_handler_keeps_state_between_lines() {
+        let mut repl = Repl::with_terminal(MockTerminal::new(""), String::from("> "), Counter(0));
+        type_keys(&mut repl, "a\rb\r");
+        let output = repl.terminal().output_str();
+        assert!(output.contains("1: a\r\n"));
+        assert!(output.contains("2: b\r\n"));
+    }
+
+    #[test]
+    fn closures_keep_captured_state_between_lines() {
+        let mut seen = Vec::new();
+        let handler = FnHandler::new(
+            move |line: String| {
+                seen.push(line);
+                Ok(seen.join(","))
+            },
+            |_| true,
+        );
+        let mut repl = Repl::with_terminal(MockTerminal::new(""), String::from("> "), handler);
+        type_keys(&mut repl, "a\rb\r");
+        assert!(repl.terminal().output_str().contains("a,b\r\n"));
+    }
+}
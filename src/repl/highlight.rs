@@ -0,0 +1,92 @@
+use std::ops::Range;
+
+/// Part of the line drawn with a style.
+pub struct StyledSpan {
+    /// Byte range of the line covered by the style.
+    pub range: Range<usize>,
+    /// Escape sequence written before the text, such as `"\x1b[34m"`. The
+    /// terminal attributes are reset after the span.
+    pub style: String,
+}
+
+impl StyledSpan {
+    pub fn new(range: Range<usize>, style: impl Into<String>) -> Self {
+        StyledSpan {
+            range,
+            style: style.into(),
+        }
+    }
+}
+
+/// Styles the line while it is being edited, for example to color keywords.
+///
+/// Only the drawing is styled; the line passed to the handler is unchanged.
+pub trait Highlighter {
+    /// Return styled spans for the line with the cursor at byte offset `pos`.
+    /// Later spans take precedence where they overlap.
+    fn highlight(&self, line: &str, pos: usize) -> Vec<StyledSpan>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{repl, type_keys};
+    use super::*;
+
+    /// Highlighter that returns the same spans for every line.
+    struct Fixed(Vec<(Range<usize>, &'static str)>);
+
+    impl Highlighter for Fixed {
+        fn highlight(&self, _line: &str, _pos: usize) -> Vec<StyledSpan> {
+            self.0
+                .iter()
+                .map(|(range, style)| StyledSpan::new(range.clone(), *style))
+                .collect()
+        }
+    }
+
+    /// Type `input` with `spans` highlighted and return the output.
+    fn draw(input: &str, spans: Vec<(Range<usize>, &'static str)>, brackets: bool) -> String {
+        let mut repl = repl();
+        if !brackets {
+            repl.set_bracket_highlight(None);
+        }
+        repl.set_highlighter(Some(Box::new(Fixed(spans))));
+        type_keys(&mut repl, input);
+        repl.terminal().output_str()
+    }
+
+    #[test]
+    fn span_ending_mid_line_is_reset() {
+        let output = draw("let x", vec![(0..3, "\x1b[34m")], false);
+        assert!(output.contains("> \x1b[34mlet\x1b[0m x"));
+    }
+
+    #[test]
+    fn later_spans_win_where_they_overlap() {
+        let spans = vec![(0..4, "\x1b[31m"), (2..6, "\x1b[32m")];
+        let output = draw("abcdef", spans, false);
+        assert!(output.contains("> \x1b[31mab\x1b[0m\x1b[32mcdef\x1b[0m"));
+    }
+
+    #[test]
+    fn bracket_styles_are_drawn_over_spans() {
+        let output = draw("(a)", vec![(0..3, "\x1b[32m")], true);
+        assert!(output.contains("> \x1b[7m(\x1b[0m\x1b[32ma)\x1b[0m"));
+    }
+
+    #[test]
+    fn styles_do_not_move_the_cursor() {
+        let move_left = |spans| {
+            let mut repl = repl();
+            repl.set_highlighter(Some(Box::new(Fixed(spans))));
+            type_keys(&mut repl, "漢字 x");
+            repl.terminal_mut().clear_output();
+            type_keys(&mut repl, "\x1b[D\x1b[D");
+            repl.terminal().output_str()
+        };
+        let plain = move_left(Vec::new());
+        let styled = move_left(vec![(0..6, "\x1b[34m")]);
+        assert_ne!(styled, plain);
+        assert_eq!(styled.replace("\x1b[34m", "").replace("\x1b[0m", ""), plain);
+    }
+}
@@ -0,0 +1,85 @@
+use super::history::History;
+
+/// Suggests text to complete the line, drawn dimmed after it.
+///
+/// The suggestion is accepted with Right or End, or one word at a time with
+/// Alt-F, when the cursor is at the end of the line.
+pub trait Hinter {
+    /// Return the text to suggest after `line` with the cursor at byte
+    /// offset `pos`, or `None` for no hint.
+    fn hint(&self, line: &str, pos: usize, history: &History) -> Option<String>;
+}
+
+/// Suggests the rest of the most recent history entry that starts with the
+/// line, like the fish shell.
+pub struct HistoryHinter;
+
+impl Hinter for HistoryHinter {
+    fn hint(&self, line: &str, pos: usize, history: &History) -> Option<String> {
+        if line.is_empty() || pos < line.len() {
+            return None;
+        }
+        history
+            .entries()
+            .iter()
+            .rev()
+            .find(|entry| entry.len() > line.len() && entry.starts_with(line))
+            .map(|entry| entry[line.len()..].to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{repl_with_history, type_keys};
+    use super::*;
+
+    #[test]
+    fn history_hinter_suggests_the_newest_match() {
+        let mut history = History::default();
+        for line in ["git status", "git stash", "git"] {
+            assert!(history.push(line.to_string()).is_ok());
+        }
+        let hinter = HistoryHinter;
+        assert_eq!(hinter.hint("git st", 6, &history).as_deref(), Some("ash"));
+        assert_eq!(hinter.hint("git st", 3, &history), None);
+        assert_eq!(hinter.hint("git", 3, &history).as_deref(), Some(" stash"));
+        assert_eq!(hinter.hint("", 0, &history), None);
+    }
+
+    #[test]
+    fn hint_is_drawn_dimmed_after_the_line() {
+        let mut repl = repl_with_history(&["git status"]);
+        type_keys(&mut repl, "git");
+        let output = repl.terminal().output_str();
+        assert!(output.contains("> git\x1b[2m status\x1b[0m\r\x1b[5C"));
+        assert_eq!(repl.line(), "git");
+    }
+
+    #[test]
+    fn right_and_end_accept_the_hint() {
+        let mut repl = repl_with_history(&["git status"]);
+        type_keys(&mut repl, "git\x1b[C");
+        assert_eq!(repl.line(), "git status");
+        assert_eq!(repl.cursor_pos(), "git status".len());
+
+        let mut repl = repl_with_history(&["git status"]);
+        type_keys(&mut repl, "git\x05");
+        assert_eq!(repl.line(), "git status");
+    }
+
+    #[test]
+    fn alt_f_accepts_one_word() {
+        let mut repl = repl_with_history(&["git commit --amend"]);
+        type_keys(&mut repl, "git\x1bf");
+        assert_eq!(repl.line(), "git commit");
+        type_keys(&mut repl, "\x1bf");
+        assert_eq!(repl.line(), "git commit --amend");
+    }
+
+    #[test]
+    fn hint_is_not_submitted() {
+        let mut repl = repl_with_history(&["git status"]);
+        type_keys(&mut repl, "git\r");
+        assert_eq!(repl.history().entries(), ["git status", "git"]);
+    }
+}
@@ -0,0 +1,436 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use super::search::SearchDirection;
+use super::{Error, Result};
+
+const DEFAULT_MAX_ENTRIES: usize = 1000;
+
+/// Lines previously submitted to the `Repl`, oldest first.
+///
+/// History files store one entry per line. Backslashes and line breaks inside
+/// an entry are escaped so multi-line entries survive a save/load round trip.
+///
+/// Every pushed line is kept by default. The `set_ignore_*`, `set_erase_dups`
+/// and `set_filter` options leave lines out, like bash's `HISTCONTROL`.
+pub struct History {
+    entries: Vec<String>,
+    max_entries: usize,
+    append_path: Option<PathBuf>,
+    ignore_empty: bool,
+    ignore_dups: bool,
+    erase_dups: bool,
+    ignore_space: bool,
+    filter: Box<dyn Fn(&str) -> bool>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            entries: Vec::new(),
+            max_entries: DEFAULT_MAX_ENTRIES,
+            append_path: None,
+            ignore_empty: false,
+            ignore_dups: false,
+            erase_dups: false,
+            ignore_space: false,
+            filter: Box::new(|_| true),
+        }
+    }
+}
+
+impl History {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    /// Find the first entry containing `query`, starting at index `start` and
+    /// moving in `direction`. Returns the entry index and the byte offset of
+    /// the match within it.
+    pub fn search(
+        &self,
+        query: &str,
+        start: usize,
+        direction: SearchDirection,
+    ) -> Option<(usize, usize)> {
+        let find = |index: usize| self.entries[index].find(query).map(|pos| (index, pos));
+        match direction {
+            SearchDirection::Reverse => {
+                let start = start.min(self.entries.len().checked_sub(1)?);
+                (0..=start).rev().find_map(find)
+            }
+            SearchDirection::Forward => (start..self.entries.len()).find_map(find),
+        }
+    }
+
+    /// Limit the number of stored entries, dropping the oldest ones first.
+    pub fn set_max_entries(&mut self, max_entries: usize) {
+        self.max_entries = max_entries;
+        self.truncate();
+    }
+
+    /// Append every pushed entry to `path` as soon as it is added.
+    /// Passing `None` turns auto-append off. The file only grows: the
+    /// `max_entries` limit applies to the entries kept in memory.
+    pub fn set_auto_append(&mut self, path: Option<PathBuf>) {
+        self.append_path = path;
+    }
+
+    /// Skip lines that are empty or only whitespace.
+    pub fn set_ignore_empty(&mut self, ignore: bool) {
+        self.ignore_empty = ignore;
+    }
+
+    /// Skip lines that repeat the newest entry.
+    pub fn set_ignore_dups(&mut self, ignore: bool) {
+        self.ignore_dups = ignore;
+    }
+
+    /// Remove older copies of a line when it is pushed again. Entries already
+    /// written to the auto-append file are left there.
+    pub fn set_erase_dups(&mut self, erase: bool) {
+        self.erase_dups = erase;
+    }
+
+    /// Skip lines that start with a space.
+    pub fn set_ignore_space(&mut self, ignore: bool) {
+        self.ignore_space = ignore;
+    }
+
+    /// Only keep lines for which `filter` returns true, for example to leave
+    /// out commands containing passwords.
+    pub fn set_filter(&mut self, filter: impl Fn(&str) -> bool + 'static) {
+        self.filter = Box::new(filter);
+    }
+
+    /// True if the options allow `line` to be added.
+    fn should_keep(&self, line: &str) -> bool {
+        if self.ignore_empty && line.trim().is_empty() {
+            return false;
+        }
+        if self.ignore_space && line.starts_with(' ') {
+            return false;
+        }
+        if self.ignore_dups && self.entries.last().is_some_and(|last| last == line) {
+            return false;
+        }
+        (self.filter)(line)
+    }
+
+    /// Add an entry, writing it to the auto-append file if one is set.
+    /// Lines left out by the history options are skipped. The entry is kept
+    /// in memory even if writing it to the file fails.
+    pub fn push(&mut self, line: String) -> Result<()> {
+        if !self.should_keep(&line) {
+            return Ok(());
+        }
+        let escaped = escape(&line);
+        if self.erase_dups {
+            self.entries.retain(|entry| *entry != line);
+        }
+        self.entries.push(line);
+        self.truncate();
+
+        let Some(path) = &self.append_path else {
+            return Ok(());
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| history_error("open", path, e))?;
+        writeln!(file, "{}", escaped).map_err(|e| history_error("write", path, e))
+    }
+
+    /// Append the entries stored in `path`. A missing file is treated as empty.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(history_error("read", path, e)),
+        };
+        self.entries.extend(contents.lines().map(unescape));
+        self.truncate();
+
+        Ok(())
+    }
+
+    /// Overwrite `path` with the current entries.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut contents = String::new();
+        for entry in &self.entries {
+            contents.push_str(&escape(entry));
+            contents.push('\n');
+        }
+        fs::write(path, contents).map_err(|e| history_error("write", path, e))
+    }
+
+    fn truncate(&mut self) {
+        if self.entries.len() > self.max_entries {
+            let excess = self.entries.len() - self.max_entries;
+            self.entries.drain(..excess);
+        }
+    }
+}
+
+fn history_error(action: &str, path: &Path, e: std::io::Error) -> Error {
+    Error::History(format!("unable to {} {}: {}", action, path.display(), e))
+}
+
+/// Escape an entry so it fits on a single line of the history file.
+fn escape(line: &str) -> String {
+    let mut escaped = String::with_capacity(line.len());
+    for c in line.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reverse `escape`. Unknown escapes are kept as written.
+fn unescape(line: &str) -> String {
+    let mut unescaped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{repl, repl_with_history, type_keys};
+    use super::*;
+
+    /// Path in the temp directory that is removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("repl-{}-{}", std::process::id(), name));
+            let _ = fs::remove_file(&path);
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn escape_round_trip() {
+        for line in [
+            "plain",
+            "a\nb",
+            "back\\slash",
+            "\\n",
+            "cr\r\n",
+            "trailing\\",
+            "",
+        ] {
+            let escaped = escape(line);
+            assert!(!escaped.contains('\n') && !escaped.contains('\r'));
+            assert_eq!(unescape(&escaped), line);
+        }
+    }
+
+    #[test]
+    fn unescape_keeps_unknown_escapes() {
+        assert_eq!(unescape("a\\tb"), "a\\tb");
+        assert_eq!(unescape("end\\"), "end\\");
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let file = TempFile::new("round-trip");
+        let mut history = History::default();
+        for line in ["one", "two\nrows", "back\\slash"] {
+            assert!(history.push(line.to_string()).is_ok());
+        }
+        assert!(history.save(&file.0).is_ok());
+
+        let mut loaded = History::default();
+        assert!(loaded.load(&file.0).is_ok());
+        assert_eq!(loaded.entries(), history.entries());
+    }
+
+    #[test]
+    fn missing_file_loads_as_empty() {
+        let file = TempFile::new("missing");
+        let mut history = History::default();
+        assert!(history.load(&file.0).is_ok());
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn auto_append_writes_each_entry() {
+        let file = TempFile::new("append");
+        let mut history = History::default();
+        history.set_auto_append(Some(file.0.clone()));
+        assert!(history.push(String::from("a\nb")).is_ok());
+        assert!(history.push(String::from("c")).is_ok());
+        assert_eq!(
+            fs::read_to_string(&file.0).ok().as_deref(),
+            Some("a\\nb\nc\n")
+        );
+    }
+
+    #[test]
+    fn failed_auto_append_keeps_the_entry() {
+        let mut history = History::default();
+        history.set_auto_append(Some(PathBuf::from("/nonexistent-dir/h")));
+        assert!(history.push(String::from("ls")).is_err());
+        assert_eq!(history.entries(), ["ls"]);
+    }
+
+    #[test]
+    fn max_entries_drops_the_oldest() {
+        let mut history = History::default();
+        for line in ["a", "b", "c"] {
+            assert!(history.push(line.to_string()).is_ok());
+        }
+        history.set_max_entries(2);
+        assert_eq!(history.entries(), ["b", "c"]);
+        assert!(history.push(String::from("d")).is_ok());
+        assert_eq!(history.entries(), ["c", "d"]);
+    }
+
+    #[test]
+    fn search_in_both_directions() {
+        let mut history = History::default();
+        for line in ["git status", "ls", "git push"] {
+            assert!(history.push(line.to_string()).is_ok());
+        }
+        assert_eq!(
+            history.search("git", 2, SearchDirection::Reverse),
+            Some((2, 0))
+        );
+        assert_eq!(
+            history.search("git", 1, SearchDirection::Reverse),
+            Some((0, 0))
+        );
+        assert_eq!(
+            history.search("s", 1, SearchDirection::Forward),
+            Some((1, 1))
+        );
+        assert_eq!(history.search("x", 0, SearchDirection::Forward), None);
+    }
+
+    /// Push each of `lines` into `history` and return what it kept.
+    fn push_all(mut history: History, lines: &[&str]) -> Vec<String> {
+        for line in lines {
+            assert!(history.push(line.to_string()).is_ok());
+        }
+        history.entries().to_vec()
+    }
+
+    #[test]
+    fn every_line_is_kept_by_default() {
+        let lines = ["", "a", "a", " b"];
+        assert_eq!(push_all(History::default(), &lines), lines);
+    }
+
+    #[test]
+    fn ignore_empty_skips_blank_lines() {
+        let mut history = History::default();
+        history.set_ignore_empty(true);
+        assert_eq!(push_all(history, &["", "  ", "a"]), ["a"]);
+    }
+
+    #[test]
+    fn ignore_dups_skips_repeats_of_the_newest_entry() {
+        let mut history = History::default();
+        history.set_ignore_dups(true);
+        assert_eq!(push_all(history, &["a", "a", "b", "a"]), ["a", "b", "a"]);
+    }
+
+    #[test]
+    fn erase_dups_removes_older_copies() {
+        let mut history = History::default();
+        history.set_erase_dups(true);
+        assert_eq!(
+            push_all(history, &["a", "b", "a", "c", "b"]),
+            ["a", "c", "b"]
+        );
+    }
+
+    #[test]
+    fn ignore_space_skips_lines_starting_with_a_space() {
+        let mut history = History::default();
+        history.set_ignore_space(true);
+        assert_eq!(push_all(history, &[" secret", "ls", "  x"]), ["ls"]);
+    }
+
+    #[test]
+    fn filter_decides_which_lines_are_kept() {
+        let mut history = History::default();
+        history.set_filter(|line| !line.contains("password"));
+        assert_eq!(
+            push_all(history, &["login", "password=1", "ls"]),
+            ["login", "ls"]
+        );
+    }
+
+    #[test]
+    fn repl_setters_apply_to_submitted_lines() {
+        let mut repl = repl();
+        repl.set_history_ignore_empty(true);
+        repl.set_history_ignore_dups(true);
+        repl.set_history_ignore_space(true);
+        repl.set_history_filter(|line| line != "skip");
+        type_keys(&mut repl, "\ra\ra\r b\rskip\rc\r");
+        assert_eq!(repl.history().entries(), ["a", "c"]);
+    }
+
+    #[test]
+    fn erase_dups_keeps_browsing_in_step() {
+        let mut repl = repl_with_history(&["a", "b"]);
+        repl.set_history_erase_dups(true);
+        type_keys(&mut repl, "a\r");
+        assert_eq!(repl.history().entries(), ["b", "a"]);
+        type_keys(&mut repl, "\x1b[A");
+        assert_eq!(repl.line(), "a");
+        type_keys(&mut repl, "\x1b[A");
+        assert_eq!(repl.line(), "b");
+        type_keys(&mut repl, "\x1b[B\x1b[B");
+        assert_eq!(repl.line(), "");
+    }
+}
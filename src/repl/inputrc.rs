@@ -0,0 +1,467 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use super::keymap::{Binding, Command, LineBuffer};
+use super::keys::{Input, KeyEvent, KeyParser};
+use super::terminal::Terminal;
+use super::{BellStyle, EditMode, Error, Repl, Result};
+
+/// Whether the lines of an `$if` block are being applied.
+struct Conditional {
+    active: bool,
+    /// The enclosing block is active, so `$else` may switch this one on.
+    parent_active: bool,
+}
+
+impl<T: Terminal> Repl<T> {
+    /// Read key bindings and settings from `$INPUTRC`, or from
+    /// `~/.inputrc` when it is not set. A missing file is not an error.
+    pub fn load_default_inputrc(&mut self) -> Result<()> {
+        let path = match std::env::var_os("INPUTRC") {
+            Some(path) => PathBuf::from(path),
+            None => match std::env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(".inputrc"),
+                None => return Ok(()),
+            },
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                self.apply_inputrc(&contents);
+                Ok(())
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::Config(format!(
+                "unable to read {}: {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+
+    /// Read key bindings and settings from an inputrc file.
+    pub fn load_inputrc(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("unable to read {}: {}", path.display(), e)))?;
+        self.apply_inputrc(&contents);
+        Ok(())
+    }
+
+    /// Apply inputrc lines. This supports a subset of GNU readline's syntax:
+    ///
+    /// - `"\C-a": beginning-of-line` and `Control-a: beginning-of-line`
+    /// - `"\C-xr": "text"` to insert text
+    /// - `set editing-mode vi|emacs` and `set bell-style none|audible|visible`
+    /// - `set keymap`, and `$if mode=...`, `$else` and `$endif` blocks
+    ///
+    /// Like readline, lines that are not understood are skipped.
+    pub fn apply_inputrc(&mut self, contents: &str) {
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        // Only the Emacs and vi insert keymaps are driven by bindings.
+        let mut keymap_bindable = true;
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(directive) = line.strip_prefix('$') {
+                let (name, arg) = split_word(directive);
+                let active = conditionals.last().is_none_or(|c| c.active);
+                match name {
+                    "if" => conditionals.push(Conditional {
+                        active: active && self.inputrc_test(arg),
+                        parent_active: active,
+                    }),
+                    "else" => {
+                        if let Some(c) = conditionals.last_mut() {
+                            c.active = c.parent_active && !c.active;
+                        }
+                    }
+                    "endif" => {
+                        conditionals.pop();
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            if conditionals.last().is_some_and(|c| !c.active) {
+                continue;
+            }
+
+            if let Some(setting) = line.strip_prefix("set ") {
+                let (name, value) = split_word(setting.trim_start());
+                let value = value.to_ascii_lowercase();
+                match name.to_ascii_lowercase().as_str() {
+                    "editing-mode" => match value.as_str() {
+                        "vi" => self.set_edit_mode(EditMode::Vi),
+                        "emacs" => self.set_edit_mode(EditMode::Emacs),
+                        _ => {}
+                    },
+                    // There is no visible bell, so like readline on a
+                    // terminal without one, fall back to the audible bell.
+                    "bell-style" => match value.as_str() {
+                        "none" => self.set_bell_style(BellStyle::None),
+                        "audible" | "visible" => self.set_bell_style(BellStyle::Audible),
+                        _ => {}
+                    },
+                    "keymap" => {
+                        keymap_bindable =
+                            matches!(value.as_str(), "emacs" | "emacs-standard" | "vi-insert");
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            if keymap_bindable && let Some((keys, binding)) = parse_binding(line) {
+                self.bind(&keys, binding);
+            }
+        }
+    }
+
+    /// Evaluate the test of an `$if` line. Only `mode=` tests can be true.
+    fn inputrc_test(&self, test: &str) -> bool {
+        match test.trim().strip_prefix("mode=") {
+            Some(mode) => match self.edit_mode() {
+                EditMode::Vi => mode == "vi",
+                EditMode::Emacs => mode == "emacs",
+            },
+            None => false,
+        }
+    }
+}
+
+/// Split off the first whitespace-separated word.
+fn split_word(s: &str) -> (&str, &str) {
+    match s.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (s, ""),
+    }
+}
+
+/// Parse a `keys: function-name` or `keys: "text"` line.
+fn parse_binding(line: &str) -> Option<(Vec<KeyEvent>, Binding)> {
+    let (keys, value) = if let Some(quoted) = line.strip_prefix('"') {
+        let end = closing_quote(quoted, '"')?;
+        let value = quoted[end + 1..].trim_start().strip_prefix(':')?;
+        (parse_key_sequence(&quoted[..end])?, value)
+    } else {
+        let (name, value) = line.split_once(':')?;
+        (parse_keyname(name.trim())?, value)
+    };
+
+    let value = value.trim();
+    let binding = match value.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let text = &value[1..];
+            let text = unescape(&text[..closing_quote(text, quote)?]);
+            let text = String::from_utf8_lossy(&text).into_owned();
+            Binding::from(move |buffer: &mut LineBuffer| buffer.insert_str(&text))
+        }
+        _ => Binding::Command(Command::from_name(split_word(value).0)?),
+    };
+    Some((keys, binding))
+}
+
+/// Byte offset of the quote that closes a string, skipping escaped quotes.
+fn closing_quote(s: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (idx, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == quote => return Some(idx),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parse a quoted key sequence such as `\C-x\C-e` or `\e[A` into keys.
+fn parse_key_sequence(sequence: &str) -> Option<Vec<KeyEvent>> {
+    keys_from_bytes(&unescape(sequence))
+}
+
+/// Parse a key name such as `Control-a`, `Meta-Rubout` or `C-M-f`.
+fn parse_keyname(name: &str) -> Option<Vec<KeyEvent>> {
+    let mut rest = name;
+    let mut control = false;
+    let mut meta = false;
+    loop {
+        let lower = rest.to_ascii_lowercase();
+        if lower.starts_with("control-") {
+            control = true;
+            rest = &rest["control-".len()..];
+        } else if lower.starts_with("c-") {
+            control = true;
+            rest = &rest["c-".len()..];
+        } else if lower.starts_with("meta-") {
+            meta = true;
+            rest = &rest["meta-".len()..];
+        } else if lower.starts_with("m-") {
+            meta = true;
+            rest = &rest["m-".len()..];
+        } else {
+            break;
+        }
+    }
+
+    let byte = match rest.to_ascii_lowercase().as_str() {
+        "del" | "rubout" => b'\x7f',
+        "esc" | "escape" => b'\x1b',
+        "lfd" | "newline" => b'\n',
+        "ret" | "return" => b'\r',
+        "space" | "spc" => b' ',
+        "tab" => b'\t',
+        _ => {
+            let mut chars = rest.chars();
+            let c = chars.next()?;
+            if chars.next().is_some() {
+                return None;
+            }
+            if !c.is_ascii() || !control {
+                let mut bytes = if meta { vec![b'\x1b'] } else { Vec::new() };
+                bytes.extend_from_slice(c.to_string().as_bytes());
+                return keys_from_bytes(&bytes);
+            }
+            c as u8
+        }
+    };
+
+    let mut bytes = if meta { vec![b'\x1b'] } else { Vec::new() };
+    bytes.push(if control { control_byte(byte) } else { byte });
+    keys_from_bytes(&bytes)
+}
+
+/// Byte sent for a character typed with Ctrl held.
+fn control_byte(c: u8) -> u8 {
+    if c == b'?' {
+        b'\x7f'
+    } else {
+        c.to_ascii_lowercase() & 0x1f
+    }
+}
+
+/// Decode readline backslash escapes into the bytes a terminal would send.
+fn unescape(s: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut chars = s.chars().peekable();
+    // Set by `\C-` and `\M-` for the next character.
+    let mut control = false;
+    let mut meta = false;
+
+    while let Some(c) = chars.next() {
+        let mut decoded = Vec::new();
+        if c == '\\' {
+            match chars.next() {
+                Some('C') if chars.peek() == Some(&'-') => {
+                    chars.next();
+                    control = true;
+                    continue;
+                }
+                Some('M') if chars.peek() == Some(&'-') => {
+                    chars.next();
+                    meta = true;
+                    continue;
+                }
+                Some('e') => decoded.push(b'\x1b'),
+                Some('a') => decoded.push(b'\x07'),
+                Some('b') => decoded.push(b'\x08'),
+                Some('d') => decoded.push(b'\x7f'),
+                Some('f') => decoded.push(b'\x0c'),
+                Some('n') => decoded.push(b'\n'),
+                Some('r') => decoded.push(b'\r'),
+                Some('t') => decoded.push(b'\t'),
+                Some('v') => decoded.push(b'\x0b'),
+                Some(d @ '0'..='7') => {
+                    let mut value = d.to_digit(8).unwrap_or(0);
+                    for _ in 0..2 {
+                        match chars.peek().and_then(|c| c.to_digit(8)) {
+                            Some(digit) => {
+                                value = value * 8 + digit;
+                                chars.next();
+                            }
+                            None => break,
+                        }
+                    }
+                    decoded.push(value as u8);
+                }
+                Some('x') => {
+                    let mut value = 0;
+                    for _ in 0..2 {
+                        match chars.peek().and_then(|c| c.to_digit(16)) {
+                            Some(digit) => {
+                                value = value * 16 + digit;
+                                chars.next();
+                            }
+                            None => break,
+                        }
+                    }
+                    decoded.push(value as u8);
+                }
+                // `\\`, `\"`, `\'` and any other escaped character.
+                Some(other) => decoded.extend_from_slice(other.to_string().as_bytes()),
+                None => decoded.push(b'\\'),
+            }
+        } else {
+            decoded.extend_from_slice(c.to_string().as_bytes());
+        }
+
+        if control && let Some(first) = decoded.first_mut() {
+            *first = control_byte(*first);
+        }
+        if meta {
+            bytes.push(b'\x1b');
+        }
+        bytes.extend(decoded);
+        control = false;
+        meta = false;
+    }
+    bytes
+}
+
+/// Decode bytes into keys the same way terminal input is decoded.
+fn keys_from_bytes(bytes: &[u8]) -> Option<Vec<KeyEvent>> {
+    let mut parser = KeyParser::default();
+    let mut keys = Vec::new();
+    for &byte in bytes {
+        if let Some(Input::Key(key)) = parser.push(byte).ok()? {
+            keys.push(key);
+        }
+    }
+    keys.extend(parser.flush_escape());
+    if keys.is_empty() { None } else { Some(keys) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::keys::{KeyCode, Modifiers};
+    use super::super::tests::{repl, type_keys};
+    use super::*;
+
+    fn command_for(repl: &Repl<impl Terminal>, keys: &[KeyEvent]) -> Option<Command> {
+        match repl.keymap().get(keys) {
+            Some(Binding::Command(command)) => Some(*command),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn binds_quoted_sequences_and_key_names() {
+        let mut repl = repl();
+        repl.apply_inputrc(
+            "# comment\n\
+             \"\\C-xe\": end-of-line\n\
+             Control-b: forward-char\n\
+             M-x: kill-line\n\
+             \"\\e[5~\": beginning-of-line\n\
+             Rubout: undo\n",
+        );
+        assert_eq!(
+            command_for(
+                &repl,
+                &[
+                    KeyEvent::ctrl('x'),
+                    KeyEvent::new(KeyCode::Char('e'), Modifiers::NONE)
+                ]
+            ),
+            Some(Command::EndOfLine)
+        );
+        assert_eq!(
+            command_for(&repl, &[KeyEvent::ctrl('b')]),
+            Some(Command::ForwardChar)
+        );
+        assert_eq!(
+            command_for(&repl, &[KeyEvent::alt('x')]),
+            Some(Command::KillLine)
+        );
+        assert_eq!(
+            command_for(&repl, &[KeyCode::PageUp.into()]),
+            Some(Command::BeginningOfLine)
+        );
+        assert_eq!(
+            command_for(&repl, &[KeyCode::Backspace.into()]),
+            Some(Command::Undo)
+        );
+    }
+
+    #[test]
+    fn macros_insert_text() {
+        let mut repl = repl();
+        repl.apply_inputrc("\"\\C-xp\": \"print(\\\"\\\")\"\nC-t: 'x'\n");
+        type_keys(&mut repl, "\x18p\x14");
+        assert_eq!(repl.line(), "print(\"\")x");
+    }
+
+    #[test]
+    fn settings() {
+        let mut repl = repl();
+        repl.apply_inputrc("set editing-mode vi\nset bell-style none\n");
+        assert!(repl.edit_mode() == EditMode::Vi);
+        type_keys(&mut repl, "\x07");
+        assert!(!repl.terminal().output_str().contains('\x07'));
+        repl.apply_inputrc("set Editing-Mode Emacs\n");
+        assert!(repl.edit_mode() == EditMode::Emacs);
+    }
+
+    #[test]
+    fn conditionals_follow_the_edit_mode() {
+        let mut repl = repl();
+        repl.apply_inputrc(
+            "$if mode=vi\n\
+             C-b: kill-line\n\
+             $else\n\
+             C-b: undo\n\
+             $if term=xterm\n\
+             C-b: yank\n\
+             $endif\n\
+             $endif\n",
+        );
+        assert_eq!(
+            command_for(&repl, &[KeyEvent::ctrl('b')]),
+            Some(Command::Undo)
+        );
+    }
+
+    #[test]
+    fn vi_command_keymap_is_skipped() {
+        let mut repl = repl();
+        repl.apply_inputrc("set keymap vi-command\nC-b: undo\nset keymap emacs\nC-f: undo\n");
+        assert_eq!(
+            command_for(&repl, &[KeyEvent::ctrl('b')]),
+            Some(Command::BackwardChar)
+        );
+        assert_eq!(
+            command_for(&repl, &[KeyEvent::ctrl('f')]),
+            Some(Command::Undo)
+        );
+    }
+
+    #[test]
+    fn unknown_lines_are_skipped() {
+        let mut repl = repl();
+        repl.apply_inputrc("C-b: no-such-command\nnonsense\n\"\\C-b: undo\nset unknown on\n");
+        assert_eq!(
+            command_for(&repl, &[KeyEvent::ctrl('b')]),
+            Some(Command::BackwardChar)
+        );
+    }
+
+    #[test]
+    fn unescapes_readline_escapes() {
+        assert_eq!(unescape("\\C-a\\M-b\\e\\t\\n"), b"\x01\x1bb\x1b\t\n");
+        assert_eq!(unescape("\\101\\x41\\\\\\\""), b"AA\\\"");
+        assert_eq!(unescape("\\C-?"), b"\x7f");
+        assert_eq!(unescape("\\M-\\C-x"), b"\x1b\x18");
+    }
+
+    #[test]
+    fn missing_file_is_an_error_only_when_named() {
+        let mut repl = repl();
+        let path = std::env::temp_dir().join("repl-no-such-inputrc");
+        assert!(matches!(repl.load_inputrc(&path), Err(Error::Config(_))));
+    }
+}
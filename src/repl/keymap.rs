@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+
+use super::keys::{KeyCode, KeyEvent, Modifiers};
+use super::search::SearchDirection;
+use super::terminal::Terminal;
+use super::{Error, Repl, Result};
+
+/// Editor command that a key can be bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Command {
+    /// Submit the line, or start a new row if it is unfinished.
+    AcceptLine,
+    /// Stop reading and return the line as it is.
+    Interrupt,
+    /// Insert the typed character.
+    SelfInsert,
+    BackwardDeleteChar,
+    DeleteChar,
+    /// Delete the character under the cursor, or stop reading on an empty line.
+    DeleteCharOrEof,
+    BeginningOfLine,
+    EndOfLine,
+    BackwardChar,
+    ForwardChar,
+    BackwardWord,
+    ForwardWord,
+    /// Move up a row, or to the previous history entry from the first row.
+    PreviousHistory,
+    /// Move down a row, or to the next history entry from the last row.
+    NextHistory,
+    /// Move to the previous history entry that starts with the text before
+    /// the cursor.
+    HistorySearchBackward,
+    /// Move to the next history entry that starts with the text before the
+    /// cursor.
+    HistorySearchForward,
+    KillLine,
+    UnixLineDiscard,
+    UnixWordRubout,
+    KillWord,
+    BackwardKillWord,
+    Yank,
+    YankPop,
+    TransposeChars,
+    Undo,
+    Redo,
+    Complete,
+    ReverseSearchHistory,
+    ForwardSearchHistory,
+    /// Switch between inserting and overwriting characters.
+    OverwriteMode,
+    /// Switch to vi normal mode. Does nothing with Emacs bindings.
+    ViMovementMode,
+}
+
+/// Readline-style name of each command, as used in inputrc files.
+const COMMAND_NAMES: &[(&str, Command)] = &[
+    ("accept-line", Command::AcceptLine),
+    ("interrupt", Command::Interrupt),
+    ("self-insert", Command::SelfInsert),
+    ("backward-delete-char", Command::BackwardDeleteChar),
+    ("delete-char", Command::DeleteChar),
+    ("delete-char-or-eof", Command::DeleteCharOrEof),
+    ("beginning-of-line", Command::BeginningOfLine),
+    ("end-of-line", Command::EndOfLine),
+    ("backward-char", Command::BackwardChar),
+    ("forward-char", Command::ForwardChar),
+    ("backward-word", Command::BackwardWord),
+    ("forward-word", Command::ForwardWord),
+    ("previous-history", Command::PreviousHistory),
+    ("next-history", Command::NextHistory),
+    ("history-search-backward", Command::HistorySearchBackward),
+    ("history-search-forward", Command::HistorySearchForward),
+    ("kill-line", Command::KillLine),
+    ("unix-line-discard", Command::UnixLineDiscard),
+    ("unix-word-rubout", Command::UnixWordRubout),
+    ("kill-word", Command::KillWord),
+    ("backward-kill-word", Command::BackwardKillWord),
+    ("yank", Command::Yank),
+    ("yank-pop", Command::YankPop),
+    ("transpose-chars", Command::TransposeChars),
+    ("undo", Command::Undo),
+    ("redo", Command::Redo),
+    ("complete", Command::Complete),
+    ("reverse-search-history", Command::ReverseSearchHistory),
+    ("forward-search-history", Command::ForwardSearchHistory),
+    ("overwrite-mode", Command::OverwriteMode),
+    ("vi-movement-mode", Command::ViMovementMode),
+];
+
+impl Command {
+    /// Look up a command by its readline name, such as `beginning-of-line`.
+    pub fn from_name(name: &str) -> Option<Command> {
+        COMMAND_NAMES
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, command)| command)
+    }
+
+    pub fn name(self) -> &'static str {
+        COMMAND_NAMES
+            .iter()
+            .find(|&&(_, command)| command == self)
+            .map_or("", |&(name, _)| name)
+    }
+}
+
+/// Line being edited, as seen by a custom key binding.
+pub struct LineBuffer {
+    line: String,
+    cursor_pos: usize,
+}
+
+impl LineBuffer {
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Byte offset of the cursor in the line.
+    pub fn cursor_pos(&self) -> usize {
+        self.cursor_pos
+    }
+
+    /// Move the cursor, clamped to the line and to a character boundary.
+    pub fn set_cursor_pos(&mut self, pos: usize) {
+        let mut pos = pos.min(self.line.len());
+        while !self.line.is_char_boundary(pos) {
+            pos -= 1;
+        }
+        self.cursor_pos = pos;
+    }
+
+    /// Insert `text` at the cursor and move the cursor past it.
+    pub fn insert_str(&mut self, text: &str) {
+        self.line.insert_str(self.cursor_pos, text);
+        self.cursor_pos += text.len();
+    }
+
+    /// Replace the whole line, putting the cursor at the end.
+    pub fn set_line(&mut self, line: String) {
+        self.cursor_pos = line.len();
+        self.line = line;
+    }
+}
+
+/// What a key sequence is bound to.
+pub enum Binding {
+    Command(Command),
+    Custom(Box<dyn FnMut(&mut LineBuffer)>),
+}
+
+impl From<Command> for Binding {
+    fn from(command: Command) -> Self {
+        Binding::Command(command)
+    }
+}
+
+impl<F: FnMut(&mut LineBuffer) + 'static> From<F> for Binding {
+    fn from(f: F) -> Self {
+        Binding::Custom(Box::new(f))
+    }
+}
+
+/// Maps key sequences to bindings.
+///
+/// A sequence is usually a single key but can be a chord such as Ctrl-X
+/// Ctrl-U. Characters typed without modifiers are inserted unless they are
+/// bound to something else.
+#[derive(Default)]
+pub struct Keymap {
+    bindings: HashMap<Vec<KeyEvent>, Binding>,
+}
+
+impl Keymap {
+    /// An empty keymap.
+    pub fn new() -> Self {
+        Keymap::default()
+    }
+
+    /// The default Emacs-style bindings.
+    pub fn emacs() -> Self {
+        use Command::*;
+
+        let ctrl = KeyEvent::ctrl;
+        let alt = KeyEvent::alt;
+        let key = KeyEvent::from;
+        let ctrl_key = |code| KeyEvent::new(code, Modifiers::CTRL);
+        let alt_key = |code| KeyEvent::new(code, Modifiers::ALT);
+
+        let mut keymap = Keymap::new();
+        for (keys, command) in [
+            (vec![key(KeyCode::Esc)], ViMovementMode),
+            (vec![key(KeyCode::Enter)], AcceptLine),
+            (vec![ctrl('c')], Interrupt),
+            (vec![key(KeyCode::Backspace)], BackwardDeleteChar),
+            (vec![ctrl('d')], DeleteCharOrEof),
+            (vec![key(KeyCode::Delete)], DeleteChar),
+            (vec![key(KeyCode::Up)], PreviousHistory),
+            (vec![key(KeyCode::Down)], NextHistory),
+            (vec![key(KeyCode::PageUp)], HistorySearchBackward),
+            (vec![key(KeyCode::PageDown)], HistorySearchForward),
+            (vec![key(KeyCode::Left)], BackwardChar),
+            (vec![key(KeyCode::Right)], ForwardChar),
+            (vec![ctrl_key(KeyCode::Left)], BackwardWord),
+            (vec![ctrl_key(KeyCode::Right)], ForwardWord),
+            (vec![alt_key(KeyCode::Left)], BackwardWord),
+            (vec![alt_key(KeyCode::Right)], ForwardWord),
+            (vec![ctrl('a')], BeginningOfLine),
+            (vec![ctrl('e')], EndOfLine),
+            (vec![key(KeyCode::Home)], BeginningOfLine),
+            (vec![key(KeyCode::End)], EndOfLine),
+            (vec![key(KeyCode::Insert)], OverwriteMode),
+            (vec![ctrl('b')], BackwardChar),
+            (vec![ctrl('f')], ForwardChar),
+            (vec![alt('b')], BackwardWord),
+            (vec![alt('f')], ForwardWord),
+            (vec![ctrl('k')], KillLine),
+            (vec![ctrl('u')], UnixLineDiscard),
+            (vec![ctrl('w')], UnixWordRubout),
+            (vec![alt('d')], KillWord),
+            (vec![alt_key(KeyCode::Backspace)], BackwardKillWord),
+            (vec![ctrl('y')], Yank),
+            (vec![alt('y')], YankPop),
+            (vec![ctrl('t')], TransposeChars),
+            (vec![ctrl('_')], Undo),
+            (vec![ctrl('z')], Undo),
+            (vec![ctrl('x'), ctrl('u')], Undo),
+            (vec![alt('/')], Redo),
+            (vec![key(KeyCode::Tab)], Complete),
+            (vec![ctrl('r')], ReverseSearchHistory),
+            (vec![ctrl('s')], ForwardSearchHistory),
+        ] {
+            keymap.bind(&keys, command);
+        }
+        keymap
+    }
+
+    /// Bind a key sequence, replacing any existing binding for it.
+    pub fn bind(&mut self, keys: &[KeyEvent], binding: impl Into<Binding>) {
+        if !keys.is_empty() {
+            self.bindings.insert(keys.to_vec(), binding.into());
+        }
+    }
+
+    /// Remove the binding for a key sequence. Returns the removed binding.
+    pub fn unbind(&mut self, keys: &[KeyEvent]) -> Option<Binding> {
+        self.bindings.remove(keys)
+    }
+
+    pub fn get(&self, keys: &[KeyEvent]) -> Option<&Binding> {
+        self.bindings.get(keys)
+    }
+
+    /// True if `keys` is the start of a longer bound sequence.
+    fn is_prefix(&self, keys: &[KeyEvent]) -> bool {
+        self.bindings
+            .keys()
+            .any(|bound| bound.len() > keys.len() && bound.starts_with(keys))
+    }
+}
+
+impl<T: Terminal> Repl<T> {
+    /// Look up the keys typed so far in the keymap and run their binding
+    /// once the sequence is complete.
+    pub(super) fn handle_mapped_key(&mut self, key: KeyEvent) -> Result<()> {
+        self.pending_keys.push(key);
+        let keys = std::mem::take(&mut self.pending_keys);
+
+        let Some(binding) = self.keymap.bindings.get_mut(&keys) else {
+            if self.keymap.is_prefix(&keys) {
+                self.pending_keys = keys;
+                return Ok(());
+            }
+            // Unbound characters are inserted and unbound chords are dropped.
+            if keys.len() == 1 && key.modifiers.is_empty() {
+                return self.run_command(Command::SelfInsert, key);
+            }
+            self.ding()?;
+            return Ok(());
+        };
+
+        match binding {
+            Binding::Command(command) => {
+                let command = *command;
+                self.run_command(command, key)
+            }
+            Binding::Custom(f) => {
+                let mut buffer = LineBuffer {
+                    line: std::mem::take(&mut self.line),
+                    cursor_pos: self.cursor_pos,
+                };
+                f(&mut buffer);
+                self.line = buffer.line;
+                self.cursor_pos = buffer.cursor_pos;
+                self.refresh_line()?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Run an editor command. `key` is the key that triggered it.
+    pub(super) fn run_command(&mut self, command: Command, key: KeyEvent) -> Result<()> {
+        match command {
+            Command::AcceptLine => return self.accept_line(),
+            Command::Interrupt => return Err(Error::Interrupted),
+            Command::SelfInsert => {
+                if let KeyCode::Char(ch) = key.code {
+                    self.input_char(ch)?;
+                }
+            }
+            Command::BackwardDeleteChar => self.backward_delete_char()?,
+            Command::DeleteChar => self.delete_char()?,
+            Command::DeleteCharOrEof => {
+                if self.line.is_empty() {
+                    return Err(Error::Eof);
+                }
+                self.delete_char()?;
+            }
+            Command::BeginningOfLine => self.beginning_of_line()?,
+            Command::EndOfLine => self.end_of_line()?,
+            Command::BackwardChar => self.backward_char()?,
+            Command::ForwardChar => self.forward_char()?,
+            Command::BackwardWord => self.backward_word()?,
+            Command::ForwardWord => self.forward_word()?,
+            Command::PreviousHistory => self.move_up()?,
+            Command::NextHistory => self.move_down()?,
+            Command::HistorySearchBackward => {
+                self.history_prefix_search(SearchDirection::Reverse)?
+            }
+            Command::HistorySearchForward => {
+                self.history_prefix_search(SearchDirection::Forward)?
+            }
+            Command::KillLine => self.kill_line()?,
+            Command::UnixLineDiscard => self.unix_line_discard()?,
+            Command::UnixWordRubout => self.unix_word_rubout()?,
+            Command::KillWord => self.kill_word()?,
+            Command::BackwardKillWord => self.backward_kill_word()?,
+            Command::Yank => self.yank()?,
+            Command::YankPop => self.yank_pop()?,
+            Command::TransposeChars => self.transpose_chars()?,
+            Command::Undo => self.undo()?,
+            Command::Redo => self.redo()?,
+            Command::Complete => self.complete()?,
+            Command::ReverseSearchHistory => self.start_search(SearchDirection::Reverse)?,
+            Command::ForwardSearchHistory => self.start_search(SearchDirection::Forward)?,
+            Command::OverwriteMode => self.overwrite = !self.overwrite,
+            Command::ViMovementMode => self.enter_vi_normal_mode()?,
+        }
+        Ok(())
+    }
+}
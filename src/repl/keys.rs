@@ -0,0 +1,463 @@
+use std::ops::BitOr;
+
+use super::utf8::{InvalidSequence, Utf8Decoder};
+
+/// Key pressed by the user, without modifiers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KeyCode {
+    /// A character. Control keys are reported as their letter with
+    /// `Modifiers::CTRL`, so Ctrl-A is `Char('a')`.
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Esc,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// Function keys F1 to F12.
+    F(u8),
+}
+
+/// Modifier keys held while a key was pressed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers(0);
+    pub const SHIFT: Modifiers = Modifiers(1);
+    pub const ALT: Modifiers = Modifiers(2);
+    pub const CTRL: Modifiers = Modifiers(4);
+
+    /// Returns true if every modifier in `other` is held.
+    pub fn contains(self, other: Modifiers) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Modifiers with those in `other` released.
+    pub fn without(self, other: Modifiers) -> Modifiers {
+        Modifiers(self.0 & !other.0)
+    }
+
+    /// Decode the modifier parameter of a CSI sequence, such as the 5 in
+    /// `ESC[1;5C`. It is one more than a mask of Shift, Alt and Ctrl.
+    fn from_param(param: u16) -> Modifiers {
+        Modifiers(param.saturating_sub(1) as u8 & 7)
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Modifiers;
+
+    fn bitor(self, rhs: Modifiers) -> Modifiers {
+        Modifiers(self.0 | rhs.0)
+    }
+}
+
+/// A key press decoded from terminal input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub modifiers: Modifiers,
+}
+
+impl KeyEvent {
+    pub fn new(code: KeyCode, modifiers: Modifiers) -> Self {
+        KeyEvent { code, modifiers }
+    }
+
+    /// A character typed with Ctrl held.
+    pub fn ctrl(c: char) -> Self {
+        KeyEvent::new(KeyCode::Char(c), Modifiers::CTRL)
+    }
+
+    /// A character typed with Alt held.
+    pub fn alt(c: char) -> Self {
+        KeyEvent::new(KeyCode::Char(c), Modifiers::ALT)
+    }
+}
+
+impl From<KeyCode> for KeyEvent {
+    fn from(code: KeyCode) -> Self {
+        KeyEvent::new(code, Modifiers::NONE)
+    }
+}
+
+/// Input decoded by the `KeyParser`.
+pub(super) enum Input {
+    Key(KeyEvent),
+    /// Text pasted between bracketed paste markers.
+    Paste(String),
+}
+
+#[derive(Default)]
+enum ParseState {
+    #[default]
+    Ground,
+    /// Read Escape, which starts a sequence or marks the next key as Alt.
+    Escape,
+    /// Inside `ESC [`, reading parameters up to the final byte.
+    Csi,
+    /// Read `ESC O`, waiting for the final byte.
+    Ss3,
+    /// Inside a bracketed paste, collecting bytes until `ESC[201~`.
+    Paste,
+}
+
+const PASTE_START: &[u8] = b"200";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Decodes bytes read from the terminal into key events.
+///
+/// Handles UTF-8 characters, control keys, Alt as an Escape prefix, and CSI
+/// (`ESC [`) and SS3 (`ESC O`) sequences with parameters, and bracketed
+/// paste. Unknown sequences are consumed and dropped.
+#[derive(Default)]
+pub(super) struct KeyParser {
+    state: ParseState,
+    /// Parameter and intermediate bytes of the CSI sequence being read, or
+    /// the bytes of a paste.
+    params: Vec<u8>,
+    /// The key being read was preceded by Escape.
+    alt: bool,
+    utf8: Utf8Decoder,
+}
+
+impl KeyParser {
+    /// Feed one byte. Returns a key or paste once its bytes are complete.
+    pub(super) fn push(&mut self, byte: u8) -> Result<Option<Input>, InvalidSequence> {
+        if let ParseState::Paste = self.state {
+            return Ok(self.paste(byte));
+        }
+        Ok(self.push_key(byte)?.map(Input::Key))
+    }
+
+    fn push_key(&mut self, byte: u8) -> Result<Option<KeyEvent>, InvalidSequence> {
+        match self.state {
+            ParseState::Ground | ParseState::Paste => self.ground(byte),
+            ParseState::Escape => match byte {
+                b'[' => {
+                    self.state = ParseState::Csi;
+                    self.params.clear();
+                    Ok(None)
+                }
+                b'O' => {
+                    self.state = ParseState::Ss3;
+                    Ok(None)
+                }
+                // The first Escape was pressed on its own.
+                b'\x1b' => Ok(Some(KeyCode::Esc.into())),
+                _ => {
+                    self.state = ParseState::Ground;
+                    self.alt = true;
+                    self.ground(byte)
+                }
+            },
+            ParseState::Csi => match byte {
+                0x20..=0x3f => {
+                    self.params.push(byte);
+                    Ok(None)
+                }
+                b'~' if self.params == PASTE_START => {
+                    self.state = ParseState::Paste;
+                    self.params.clear();
+                    Ok(None)
+                }
+                0x40..=0x7e => {
+                    self.state = ParseState::Ground;
+                    Ok(self.csi_key(byte))
+                }
+                // Anything else aborts the sequence and is read as new input.
+                _ => {
+                    self.state = ParseState::Ground;
+                    self.ground(byte)
+                }
+            },
+            ParseState::Ss3 => {
+                self.state = ParseState::Ground;
+                Ok(final_key(byte, Modifiers::NONE))
+            }
+        }
+    }
+
+    /// True when an Escape has been read that may start a sequence.
+    pub(super) fn is_escape_pending(&self) -> bool {
+        matches!(self.state, ParseState::Escape)
+    }
+
+    /// Report a pending Escape as a key press on its own.
+    pub(super) fn flush_escape(&mut self) -> Option<KeyEvent> {
+        if !self.is_escape_pending() {
+            return None;
+        }
+        self.state = ParseState::Ground;
+        Some(KeyCode::Esc.into())
+    }
+
+    /// Collect a pasted byte, returning the text once the end marker is read.
+    /// Line endings become `\n`.
+    fn paste(&mut self, byte: u8) -> Option<Input> {
+        self.params.push(byte);
+        if !self.params.ends_with(PASTE_END) {
+            return None;
+        }
+        self.state = ParseState::Ground;
+        let bytes = std::mem::take(&mut self.params);
+        let text = String::from_utf8_lossy(&bytes[..bytes.len() - PASTE_END.len()])
+            .replace("\r\n", "\n")
+            .replace('\r', "\n");
+        Some(Input::Paste(text))
+    }
+
+    fn ground(&mut self, byte: u8) -> Result<Option<KeyEvent>, InvalidSequence> {
+        let alt = std::mem::take(&mut self.alt);
+        let Some(ch) = self.utf8.push(byte)? else {
+            self.alt = alt;
+            return Ok(None);
+        };
+
+        let (code, modifiers) = match ch {
+            '\r' | '\n' => (KeyCode::Enter, Modifiers::NONE),
+            '\t' => (KeyCode::Tab, Modifiers::NONE),
+            '\x08' | '\x7f' => (KeyCode::Backspace, Modifiers::NONE),
+            '\x1b' => {
+                self.state = ParseState::Escape;
+                return Ok(None);
+            }
+            '\0' => (KeyCode::Char(' '), Modifiers::CTRL),
+            '\x01'..='\x1a' => (
+                KeyCode::Char((ch as u8 - 1 + b'a') as char),
+                Modifiers::CTRL,
+            ),
+            // Ctrl-\, Ctrl-], Ctrl-^ and Ctrl-_.
+            '\x1c'..='\x1f' => (KeyCode::Char((ch as u8 + 0x40) as char), Modifiers::CTRL),
+            _ => (KeyCode::Char(ch), Modifiers::NONE),
+        };
+        let modifiers = if alt {
+            modifiers | Modifiers::ALT
+        } else {
+            modifiers
+        };
+        Ok(Some(KeyEvent::new(code, modifiers)))
+    }
+
+    /// Decode a finished CSI sequence such as `ESC[A`, `ESC[1;5C` or `ESC[3~`.
+    fn csi_key(&mut self, final_byte: u8) -> Option<KeyEvent> {
+        let params = std::mem::take(&mut self.params);
+        // Private parameters and intermediates are not used by any key.
+        if !params.iter().all(|b| b.is_ascii_digit() || *b == b';') {
+            return None;
+        }
+        let params = String::from_utf8_lossy(&params);
+        let mut numbers = params.split(';').map(|p| p.parse::<u16>().ok());
+        let first = numbers.next().flatten();
+        let modifiers = numbers
+            .next()
+            .flatten()
+            .map_or(Modifiers::NONE, Modifiers::from_param);
+
+        if final_byte != b'~' {
+            return final_key(final_byte, modifiers);
+        }
+        let code = match first? {
+            1 | 7 => KeyCode::Home,
+            2 => KeyCode::Insert,
+            3 => KeyCode::Delete,
+            4 | 8 => KeyCode::End,
+            5 => KeyCode::PageUp,
+            6 => KeyCode::PageDown,
+            n @ 11..=15 => KeyCode::F((n - 10) as u8),
+            n @ 17..=21 => KeyCode::F((n - 11) as u8),
+            n @ 23..=24 => KeyCode::F((n - 12) as u8),
+            _ => return None,
+        };
+        Some(KeyEvent::new(code, modifiers))
+    }
+}
+
+/// Key named by the final byte of a CSI or SS3 sequence.
+fn final_key(final_byte: u8, modifiers: Modifiers) -> Option<KeyEvent> {
+    let code = match final_byte {
+        b'A' => KeyCode::Up,
+        b'B' => KeyCode::Down,
+        b'C' => KeyCode::Right,
+        b'D' => KeyCode::Left,
+        b'H' => KeyCode::Home,
+        b'F' => KeyCode::End,
+        b'P' => KeyCode::F(1),
+        b'Q' => KeyCode::F(2),
+        b'R' => KeyCode::F(3),
+        b'S' => KeyCode::F(4),
+        // Shift-Tab.
+        b'Z' => return Some(KeyEvent::new(KeyCode::Tab, modifiers | Modifiers::SHIFT)),
+        _ => return None,
+    };
+    Some(KeyEvent::new(code, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode `bytes` into the keys they produce, ignoring pastes.
+    fn keys(bytes: &[u8]) -> Vec<KeyEvent> {
+        let mut parser = KeyParser::default();
+        bytes
+            .iter()
+            .filter_map(|&b| match parser.push(b) {
+                Ok(Some(Input::Key(key))) => Some(key),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn key(code: KeyCode, modifiers: Modifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn characters_and_control_keys() {
+        assert_eq!(
+            keys("aé".as_bytes()),
+            [KeyCode::Char('a').into(), KeyCode::Char('é').into()]
+        );
+        assert_eq!(
+            keys(b"\x01\x1a"),
+            [KeyEvent::ctrl('a'), KeyEvent::ctrl('z')]
+        );
+        assert_eq!(
+            keys(b"\x1f\x00"),
+            [KeyEvent::ctrl('_'), KeyEvent::ctrl(' ')]
+        );
+        assert_eq!(
+            keys(b"\r\n\t\x7f\x08"),
+            [
+                KeyCode::Enter.into(),
+                KeyCode::Enter.into(),
+                KeyCode::Tab.into(),
+                KeyCode::Backspace.into(),
+                KeyCode::Backspace.into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn escape_prefix_is_alt() {
+        assert_eq!(keys(b"\x1bf"), [KeyEvent::alt('f')]);
+        assert_eq!(keys(b"\x1b\x7f"), [key(KeyCode::Backspace, Modifiers::ALT)]);
+        assert_eq!(
+            keys(b"\x1b\x01"),
+            [key(KeyCode::Char('a'), Modifiers::CTRL | Modifiers::ALT)]
+        );
+        assert_eq!(keys(b"\x1b\x1b"), [KeyCode::Esc.into()]);
+    }
+
+    #[test]
+    fn lone_escape_is_flushed() {
+        let mut parser = KeyParser::default();
+        assert!(matches!(parser.push(0x1b), Ok(None)));
+        assert!(parser.is_escape_pending());
+        assert_eq!(parser.flush_escape(), Some(KeyCode::Esc.into()));
+        assert!(!parser.is_escape_pending());
+        assert_eq!(parser.flush_escape(), None);
+    }
+
+    #[test]
+    fn csi_and_ss3_keys() {
+        assert_eq!(
+            keys(b"\x1b[A\x1b[B\x1b[C\x1b[D\x1b[H\x1b[F"),
+            [
+                KeyCode::Up.into(),
+                KeyCode::Down.into(),
+                KeyCode::Right.into(),
+                KeyCode::Left.into(),
+                KeyCode::Home.into(),
+                KeyCode::End.into(),
+            ]
+        );
+        assert_eq!(
+            keys(b"\x1bOA\x1bOP"),
+            [KeyCode::Up.into(), KeyCode::F(1).into()]
+        );
+        assert_eq!(keys(b"\x1b[Z"), [key(KeyCode::Tab, Modifiers::SHIFT)]);
+    }
+
+    #[test]
+    fn tilde_keys() {
+        assert_eq!(
+            keys(b"\x1b[1~\x1b[2~\x1b[3~\x1b[4~\x1b[5~\x1b[6~\x1b[7~\x1b[8~"),
+            [
+                KeyCode::Home.into(),
+                KeyCode::Insert.into(),
+                KeyCode::Delete.into(),
+                KeyCode::End.into(),
+                KeyCode::PageUp.into(),
+                KeyCode::PageDown.into(),
+                KeyCode::Home.into(),
+                KeyCode::End.into(),
+            ]
+        );
+        assert_eq!(
+            keys(b"\x1b[11~\x1b[15~\x1b[17~\x1b[21~\x1b[23~\x1b[24~"),
+            [1, 5, 6, 10, 11, 12].map(|n| KeyCode::F(n).into())
+        );
+        assert_eq!(keys(b"\x1b[99~x"), [KeyCode::Char('x').into()]);
+    }
+
+    #[test]
+    fn modifier_parameters() {
+        assert_eq!(keys(b"\x1b[1;5C"), [key(KeyCode::Right, Modifiers::CTRL)]);
+        assert_eq!(keys(b"\x1b[1;3D"), [key(KeyCode::Left, Modifiers::ALT)]);
+        assert_eq!(keys(b"\x1b[1;2A"), [key(KeyCode::Up, Modifiers::SHIFT)]);
+        assert_eq!(
+            keys(b"\x1b[3;6~"),
+            [key(KeyCode::Delete, Modifiers::CTRL | Modifiers::SHIFT)]
+        );
+    }
+
+    #[test]
+    fn unknown_sequences_are_dropped() {
+        assert_eq!(keys(b"\x1b[?25hx"), [KeyCode::Char('x').into()]);
+        assert_eq!(keys(b"\x1b[1;5Xy"), [KeyCode::Char('y').into()]);
+    }
+
+    #[test]
+    fn invalid_utf8_is_an_error() {
+        let mut parser = KeyParser::default();
+        assert!(parser.push(0xff).is_err());
+        assert!(matches!(parser.push(b'a'), Ok(Some(Input::Key(_)))));
+    }
+
+    #[test]
+    fn bracketed_paste() {
+        let mut parser = KeyParser::default();
+        let mut pasted = None;
+        for &b in b"\x1b[200~a\r\nb\rc\x1b[A\x1b[201~" {
+            if let Ok(Some(Input::Paste(text))) = parser.push(b) {
+                pasted = Some(text);
+            }
+        }
+        assert_eq!(pasted.as_deref(), Some("a\nb\nc\x1b[A"));
+        assert!(matches!(parser.push(b'x'), Ok(Some(Input::Key(_)))));
+    }
+
+    #[test]
+    fn modifiers() {
+        let both = Modifiers::CTRL | Modifiers::ALT;
+        assert!(both.contains(Modifiers::CTRL));
+        assert!(!both.contains(Modifiers::SHIFT));
+        assert_eq!(both.without(Modifiers::ALT), Modifiers::CTRL);
+        assert!(Modifiers::NONE.is_empty());
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use super::terminal::Terminal;
+use super::{Repl, Result};
+
+const DEFAULT_MAX_ENTRIES: usize = 60;
+
+/// What a key did to the kill ring, checked by the key after it.
+#[derive(Clone, Default, PartialEq, Eq)]
+enum Action {
+    #[default]
+    Other,
+    Kill,
+    /// Text from the ring was inserted over this range of the line.
+    Yank(Range<usize>),
+}
+
+/// Killed text, newest first.
+///
+/// Consecutive kills are joined into one entry. After a yank, yank-pop
+/// replaces the yanked text with the next older entry.
+pub(super) struct KillRing {
+    entries: VecDeque<String>,
+    max_entries: usize,
+    /// Entry inserted by the last yank or yank-pop.
+    yank_index: usize,
+    last: Action,
+    current: Action,
+}
+
+impl Default for KillRing {
+    fn default() -> Self {
+        KillRing {
+            entries: VecDeque::new(),
+            max_entries: DEFAULT_MAX_ENTRIES,
+            yank_index: 0,
+            last: Action::Other,
+            current: Action::Other,
+        }
+    }
+}
+
+impl KillRing {
+    /// Called before each key so kills and yanks can tell whether they
+    /// directly follow another one.
+    pub(super) fn start_key(&mut self) {
+        self.last = std::mem::take(&mut self.current);
+    }
+
+    /// Store killed text. Right after another kill the text is joined to
+    /// the newest entry, before it when killing backwards.
+    pub(super) fn kill(&mut self, text: &str, backward: bool) {
+        match self.entries.front_mut() {
+            Some(newest) if self.last == Action::Kill => {
+                if backward {
+                    newest.insert_str(0, text);
+                } else {
+                    newest.push_str(text);
+                }
+            }
+            _ => self.push(text),
+        }
+        self.current = Action::Kill;
+    }
+
+    /// Store text as a new entry without joining it to earlier kills.
+    pub(super) fn push(&mut self, text: &str) {
+        self.entries.push_front(text.to_string());
+        self.entries.truncate(self.max_entries);
+    }
+
+    /// The most recently killed text.
+    pub(super) fn latest(&self) -> Option<&str> {
+        self.entries.front().map(String::as_str)
+    }
+
+    /// Note that entry `index` was inserted over `range`.
+    fn yanked(&mut self, index: usize, range: Range<usize>) {
+        self.yank_index = index;
+        self.current = Action::Yank(range);
+    }
+}
+
+impl<T: Terminal> Repl<T> {
+    /// Insert the most recently killed text at the cursor.
+    pub(super) fn yank(&mut self) -> Result<()> {
+        let Some(text) = self.kill_ring.latest() else {
+            return Ok(());
+        };
+        let text = text.to_string();
+        let start = self.cursor_pos;
+        self.line.insert_str(start, &text);
+        self.cursor_pos += text.len();
+        self.kill_ring.yanked(0, start..self.cursor_pos);
+        self.refresh_line()
+    }
+
+    /// Replace the text just yanked with the next older kill. Does nothing
+    /// unless the previous key was a yank or yank-pop.
+    pub(super) fn yank_pop(&mut self) -> Result<()> {
+        let Action::Yank(range) = self.kill_ring.last.clone() else {
+            return Ok(());
+        };
+        let ring = &self.kill_ring;
+        let index = (ring.yank_index + 1) % ring.entries.len();
+        let text = ring.entries[index].clone();
+        self.line.replace_range(range.clone(), &text);
+        self.cursor_pos = range.start + text.len();
+        self.kill_ring.yanked(index, range.start..self.cursor_pos);
+        self.refresh_line()
+    }
+}
@@ -0,0 +1,296 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use super::brackets::{bracket_pairs, is_closer};
+use super::terminal::Terminal;
+use super::vi::ViMode;
+use super::{Repl, Result};
+
+/// Screen position as `(row, column)` relative to the first prompt row.
+type Position = (usize, usize);
+
+/// Style of the hint drawn after the line.
+const HINT_STYLE: &str = "\x1b[2m";
+
+/// Position of the terminal cursor after writing `text` from `pos` on a
+/// terminal `columns` wide. A column equal to `columns` means the terminal is
+/// waiting to wrap before the next character.
+fn advance(mut pos: Position, text: &str, columns: usize) -> Position {
+    for grapheme in text.graphemes(true) {
+        if grapheme == "\n" {
+            pos = (pos.0 + 1, 0);
+            continue;
+        }
+        // Tabs move to the next multiple of 8 and never wrap.
+        if grapheme == "\t" {
+            pos.1 = ((pos.1 / 8 + 1) * 8)
+                .min(columns.saturating_sub(1))
+                .max(pos.1);
+            continue;
+        }
+        let width = grapheme.width();
+        if pos.1 + width > columns {
+            pos = (pos.0 + 1, 0);
+        }
+        pos.1 += width;
+    }
+    pos
+}
+
+/// Resolve a pending wrap to the start of the next row.
+fn normalize(pos: Position, columns: usize) -> Position {
+    if pos.1 >= columns {
+        (pos.0 + 1, 0)
+    } else {
+        pos
+    }
+}
+
+impl<T: Terminal> Repl<T> {
+    /// Prompt drawn before the first row of the buffer.
+    fn first_prompt(&self) -> String {
+        let indicator = match self.vi_mode() {
+            Some(ViMode::Insert) => self.vi_insert_indicator.as_str(),
+            Some(ViMode::Normal) => self.vi_normal_indicator.as_str(),
+            None => "",
+        };
+        match &self.search {
+            Some(search) => search.prompt(),
+            None => format!("{}{}", indicator, self.prompt),
+        }
+    }
+
+    /// Screen position of byte offset `pos` in the buffer.
+    fn position_of(&self, pos: usize, columns: usize) -> Position {
+        let start = advance((0, 0), &self.first_prompt(), columns);
+        self.advance_rows(start, &self.line[..pos], columns)
+    }
+
+    /// Position after writing `text` from `pos`, with each line break
+    /// followed by the continuation prompt.
+    fn advance_rows(&self, mut pos: Position, text: &str, columns: usize) -> Position {
+        for (i, row) in text.split('\n').enumerate() {
+            if i > 0 {
+                pos = advance((pos.0 + 1, 0), &self.continuation_prompt, columns);
+            }
+            pos = advance(pos, row, columns);
+        }
+        pos
+    }
+
+    /// Screen position just past the buffer and the hint drawn after it.
+    fn end_position(&self, columns: usize) -> Position {
+        let end = self.position_of(self.line.len(), columns);
+        match &self.hint {
+            Some(hint) => self.advance_rows(end, hint, columns),
+            None => end,
+        }
+    }
+
+    /// Append `text` to `output`, starting each row after the first with the
+    /// continuation prompt.
+    fn push_rows(&self, output: &mut String, text: &str, style: Option<&str>) {
+        for (i, row) in text.split('\n').enumerate() {
+            if i > 0 {
+                output.push_str("\r\n");
+                output.push_str(&self.continuation_prompt);
+            }
+            match style {
+                Some(style) if !row.is_empty() => {
+                    output.push_str(style);
+                    output.push_str(row);
+                    output.push_str("\x1b[0m");
+                }
+                _ => output.push_str(row),
+            }
+        }
+    }
+
+    /// The buffer with the highlighter's styles applied, and unmatched
+    /// closing brackets and the partner of the bracket on or just before the
+    /// cursor wrapped in their styles. Styles end before each line break so
+    /// they do not run into the continuation prompt.
+    fn styled_line(&self) -> String {
+        let spans = match &self.highlighter {
+            Some(highlighter) => highlighter.highlight(&self.line, self.cursor_pos),
+            None => Vec::new(),
+        };
+        if spans.is_empty() && self.bracket_highlight.is_none() {
+            return self.line.clone();
+        }
+
+        // Style of each byte; only character starts are read.
+        let mut styles: Vec<Option<&str>> = vec![None; self.line.len()];
+        for span in &spans {
+            let end = span.range.end.min(self.line.len());
+            for style in styles.iter_mut().take(end).skip(span.range.start) {
+                *style = Some(span.style.as_str());
+            }
+        }
+        if let Some(highlight) = &self.bracket_highlight {
+            let pairs = bracket_pairs(&self.line);
+            for &(idx, partner) in &pairs {
+                if partner.is_none() && is_closer(self.line.as_bytes()[idx] as char) {
+                    styles[idx] = Some(highlight.unmatched.as_str());
+                }
+            }
+            let at_cursor = pairs
+                .iter()
+                .find(|(idx, _)| *idx == self.cursor_pos)
+                .or_else(|| pairs.iter().find(|(idx, _)| *idx + 1 == self.cursor_pos));
+            if let Some(&(_, Some(partner))) = at_cursor {
+                styles[partner] = Some(highlight.matched.as_str());
+            }
+        }
+
+        let mut styled = String::with_capacity(self.line.len());
+        let mut current = None;
+        for (idx, c) in self.line.char_indices() {
+            let style = if c == '\n' { None } else { styles[idx] };
+            if style != current {
+                if current.is_some() {
+                    styled.push_str("\x1b[0m");
+                }
+                if let Some(style) = style {
+                    styled.push_str(style);
+                }
+                current = style;
+            }
+            styled.push(c);
+        }
+        if current.is_some() {
+            styled.push_str("\x1b[0m");
+        }
+        styled
+    }
+
+    /// Terminal width, or 80 columns when it cannot be read.
+    pub(super) fn columns(&self) -> usize {
+        match self.terminal.size() {
+            Ok((columns, _)) if columns > 0 => columns,
+            _ => 80,
+        }
+    }
+
+    /// Redraw the prompt, every row of the buffer and the hint, then place
+    /// the terminal cursor on the row and display column that match
+    /// `cursor_pos`.
+    pub(super) fn refresh_line(&mut self) -> Result<()> {
+        self.hint = match &self.hinter {
+            Some(hinter) if self.search.is_none() => {
+                hinter.hint(&self.line, self.cursor_pos, &self.history)
+            }
+            _ => None,
+        };
+        self.draw_line()
+    }
+
+    /// Redraw like `refresh_line`, keeping the current hint.
+    pub(super) fn draw_line(&mut self) -> Result<()> {
+        let columns = self.columns();
+        let mut output = String::new();
+        if self.cursor_row > 0 {
+            output.push_str(&format!("\x1b[{}A", self.cursor_row));
+        }
+        output.push_str("\r\x1b[J");
+        output.push_str(&self.first_prompt());
+        self.push_rows(&mut output, &self.styled_line(), None);
+        if let Some(hint) = &self.hint {
+            self.push_rows(&mut output, hint, Some(HINT_STYLE));
+        }
+
+        let mut end = self.end_position(columns);
+        if end.1 >= columns {
+            output.push_str("\r\n");
+            end = normalize(end, columns);
+        }
+        let cursor = normalize(self.position_of(self.cursor_pos, columns), columns);
+        if cursor != end {
+            if end.0 > cursor.0 {
+                output.push_str(&format!("\x1b[{}A", end.0 - cursor.0));
+            }
+            output.push('\r');
+            if cursor.1 > 0 {
+                output.push_str(&format!("\x1b[{}C", cursor.1));
+            }
+        }
+        self.cursor_row = cursor.0;
+
+        self.terminal.write(output.as_bytes())?;
+        self.terminal.flush()
+    }
+
+    /// Move the terminal cursor to a fresh row below the buffer so output can
+    /// be written without overwriting it.
+    pub(super) fn move_below_input(&mut self) -> Result<()> {
+        let columns = self.columns();
+        let end = normalize(self.end_position(columns), columns);
+        let mut output = String::new();
+        if end.0 > self.cursor_row {
+            output.push_str(&format!("\x1b[{}B", end.0 - self.cursor_row));
+        }
+        // A buffer that exactly fills its last row already ends on a fresh row.
+        if end.0 > 0 && end.1 == 0 {
+            output.push('\r');
+        } else {
+            output.push_str("\r\n");
+        }
+        self.cursor_row = 0;
+
+        self.terminal.write(output.as_bytes())?;
+        self.terminal.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{repl, type_keys};
+    use super::*;
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        assert_eq!(advance((0, 0), "漢字", 80), (0, 4));
+        assert_eq!(advance((0, 0), "e\u{301}漢", 80), (0, 3));
+    }
+
+    #[test]
+    fn wide_character_that_does_not_fit_wraps_whole() {
+        assert_eq!(advance((0, 0), "abc漢", 4), (1, 2));
+    }
+
+    #[test]
+    fn text_that_fills_a_row_waits_to_wrap() {
+        assert_eq!(advance((0, 0), "abcd", 4), (0, 4));
+        assert_eq!(normalize((0, 4), 4), (1, 0));
+        assert_eq!(normalize((0, 3), 4), (0, 3));
+    }
+
+    #[test]
+    fn cursor_crosses_rows_by_display_column() {
+        let mut repl = repl();
+        repl.terminal_mut().set_size(10, 24);
+        type_keys(&mut repl, "漢字漢字漢");
+        assert_eq!(repl.cursor_row, 1);
+        repl.terminal_mut().clear_output();
+        type_keys(&mut repl, "\x1b[D\x1b[D");
+        assert_eq!(repl.cursor_row, 0);
+        assert!(
+            repl.terminal()
+                .output_str()
+                .ends_with("\r\x1b[8C\x1b[?2004l")
+        );
+    }
+
+    #[test]
+    fn line_that_exactly_fills_a_row() {
+        let mut repl = repl();
+        repl.terminal_mut().set_size(10, 24);
+        type_keys(&mut repl, "12345678");
+        assert_eq!(repl.cursor_row, 1);
+        assert!(repl.terminal().output_str().contains("> 12345678\r\n"));
+
+        type_keys(&mut repl, "\r");
+        let output = repl.terminal().output_str();
+        assert!(output.contains("> 12345678\r\n\r12345678\r\n"));
+    }
+}
@@ -0,0 +1,191 @@
+use super::keys::{KeyCode, KeyEvent, Modifiers};
+use super::terminal::Terminal;
+use super::{Repl, Result};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SearchDirection {
+    Reverse,
+    Forward,
+}
+
+/// Incremental history search started with Ctrl-R or Ctrl-S.
+pub(super) struct SearchState {
+    query: String,
+    direction: SearchDirection,
+    /// History index of the entry currently shown, if any matched.
+    match_index: Option<usize>,
+    failed: bool,
+    /// Line and cursor restored when the search is cancelled.
+    original_line: String,
+    original_cursor: usize,
+}
+
+impl SearchState {
+    /// Prompt shown in place of the normal prompt while searching.
+    pub(super) fn prompt(&self) -> String {
+        let failed = if self.failed { "failed " } else { "" };
+        let name = match self.direction {
+            SearchDirection::Reverse => "reverse-i-search",
+            SearchDirection::Forward => "i-search",
+        };
+        format!("({}{})'{}': ", failed, name, self.query)
+    }
+}
+
+impl<T: Terminal> Repl<T> {
+    /// Enter search mode, keeping the current line so it can be restored.
+    pub(super) fn start_search(&mut self, direction: SearchDirection) -> Result<()> {
+        self.search = Some(SearchState {
+            query: String::new(),
+            direction,
+            match_index: None,
+            failed: false,
+            original_line: self.line.clone(),
+            original_cursor: self.cursor_pos,
+        });
+        self.refresh_line()
+    }
+
+    /// Handle a key pressed while searching. Keys that do not belong to the
+    /// search accept the current match and are then handled as normal input.
+    pub(super) fn handle_search_key(&mut self, key: KeyEvent) -> Result<()> {
+        match (key.code, key.modifiers) {
+            // Ctrl-R: find an older match.
+            (KeyCode::Char('r'), Modifiers::CTRL) => self.search_again(SearchDirection::Reverse)?,
+            // Ctrl-S: find a newer match.
+            (KeyCode::Char('s'), Modifiers::CTRL) => self.search_again(SearchDirection::Forward)?,
+            // Ctrl-G, Ctrl-C: cancel and restore the original line.
+            (KeyCode::Char('g'), Modifiers::CTRL) | (KeyCode::Char('c'), Modifiers::CTRL) => {
+                if let Some(search) = self.search.take() {
+                    self.line = search.original_line;
+                    self.cursor_pos = search.original_cursor;
+                }
+                self.refresh_line()?;
+            }
+            // Backspace: shorten the query and search again from the start.
+            (KeyCode::Backspace, Modifiers::NONE) => {
+                if let Some(search) = self.search.as_mut() {
+                    search.query.pop();
+                    search.match_index = None;
+                }
+                self.update_search(None)?;
+            }
+            (KeyCode::Char(ch), Modifiers::NONE) => self.search_push_char(ch)?,
+            _ => {
+                self.accept_search()?;
+                return self.handle_key(key);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Extend the query with a typed character.
+    pub(super) fn search_push_char(&mut self, ch: char) -> Result<()> {
+        if ch.is_control() {
+            return Ok(());
+        }
+        let start = match self.search.as_mut() {
+            Some(search) => {
+                search.query.push(ch);
+                search.match_index
+            }
+            None => return Ok(()),
+        };
+        self.update_search(start)
+    }
+
+    /// Leave search mode with the matched entry as the line being edited.
+    pub(super) fn accept_search(&mut self) -> Result<()> {
+        if let Some(search) = self.search.take()
+            && let Some(index) = search.match_index
+        {
+            self.save_history_edit(self.lines_pos, search.original_line);
+            self.lines_pos = index;
+        }
+        self.refresh_line()
+    }
+
+    /// Step to the next match in `direction`. An empty query reuses the
+    /// previous search.
+    fn search_again(&mut self, direction: SearchDirection) -> Result<()> {
+        let last_query = self.last_search_query.clone();
+        let history_len = self.history.len();
+        let Some(search) = self.search.as_mut() else {
+            return Ok(());
+        };
+        if search.query.is_empty() {
+            search.query = last_query;
+        }
+        search.direction = direction;
+        let start = match search.match_index {
+            Some(index) => {
+                let next = match direction {
+                    SearchDirection::Reverse => index.checked_sub(1),
+                    SearchDirection::Forward => Some(index + 1).filter(|&i| i < history_len),
+                };
+                if next.is_none() {
+                    search.failed = true;
+                    self.ding()?;
+                    return self.refresh_line();
+                }
+                next
+            }
+            None => None,
+        };
+        self.update_search(start)
+    }
+
+    /// Search the history from `start`, or from where history browsing left
+    /// off when `start` is `None`, and show the first match.
+    fn update_search(&mut self, start: Option<usize>) -> Result<()> {
+        let Some(search) = self.search.as_mut() else {
+            return Ok(());
+        };
+        let start = start.unwrap_or(match search.direction {
+            SearchDirection::Reverse => self.history.len().saturating_sub(1),
+            SearchDirection::Forward => self.lines_pos,
+        });
+        if search.query.is_empty() {
+            search.failed = false;
+            return self.refresh_line();
+        }
+        self.last_search_query = search.query.clone();
+
+        match self.history.search(&search.query, start, search.direction) {
+            Some((index, pos)) => {
+                search.match_index = Some(index);
+                search.failed = false;
+                self.line = self.history.entries()[index].clone();
+                self.cursor_pos = pos;
+            }
+            None => {
+                search.failed = true;
+                self.ding()?;
+            }
+        }
+        self.refresh_line()
+    }
+
+    /// Move to the next history entry in `direction` that starts with the
+    /// text before the cursor, leaving the cursor after that text. Searching
+    /// forward past the newest entry can return to the line typed before
+    /// browsing.
+    pub(super) fn history_prefix_search(&mut self, direction: SearchDirection) -> Result<()> {
+        let prefix = &self.line[..self.cursor_pos];
+        let matches = |&index: &usize| {
+            let entry = self.history_line(index);
+            entry.starts_with(prefix) && entry != self.line
+        };
+        let found = match direction {
+            SearchDirection::Reverse => (0..self.lines_pos).rev().find(matches),
+            SearchDirection::Forward => (self.lines_pos + 1..=self.history.len()).find(matches),
+        };
+
+        let Some(index) = found else {
+            return self.ding();
+        };
+        self.select_history_entry(index);
+        self.refresh_line()
+    }
+}
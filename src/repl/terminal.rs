@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+
+use term_manager::TermManager;
+
+use super::{Error, Result};
+
+/// Backend the `Repl` reads keys from and draws to.
+pub trait Terminal {
+    /// Block until a single byte of input is available and return it.
+    fn read_byte(&mut self) -> Result<u8>;
+
+    /// Wait up to `timeout` for input and return true if a byte can be read
+    /// without blocking.
+    fn byte_ready(&mut self, timeout: Duration) -> Result<bool>;
+
+    /// Write bytes to the terminal output.
+    fn write(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Flush buffered output to the terminal.
+    fn flush(&mut self) -> Result<()>;
+
+    /// Terminal size as `(columns, rows)`.
+    fn size(&self) -> Result<(usize, usize)>;
+
+    /// Stop the terminal from echoing and buffering input.
+    fn enable_raw_mode(&mut self) -> Result<()>;
+
+    /// Restore line buffering and echo.
+    fn disable_raw_mode(&mut self) -> Result<()>;
+}
+
+/// The terminal attached to stdin and stdout.
+pub struct StdTerminal {
+    inner: TermManager,
+    /// Terminal mode of stdin before `enable_raw_mode`, put back by
+    /// `disable_raw_mode`.
+    saved: Option<libc::termios>,
+}
+
+impl StdTerminal {
+    pub fn new() -> Result<Self> {
+        let inner = TermManager::new()
+            .map_err(|e| Error::InitFail(format!("unable to open the terminal: {}", e)))?;
+        Ok(StdTerminal { inner, saved: None })
+    }
+}
+
+impl Terminal for StdTerminal {
+    // Stdin is read through its file descriptor rather than `TermManager::read`
+    // so that no input sits in a userspace buffer where `byte_ready` can't see it.
+    fn read_byte(&mut self) -> Result<u8> {
+        let fd = self.inner.get_stdin().as_raw_fd();
+        let mut buf = [0u8; 1];
+        loop {
+            let res = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), 1) };
+            match res {
+                1 => return Ok(buf[0]),
+                0 => return Err(Error::IoRead(String::from("read 0 bytes from stdin"))),
+                _ => {
+                    let e = std::io::Error::last_os_error();
+                    if e.kind() != std::io::ErrorKind::Interrupted {
+                        return Err(Error::IoRead(format!("unable to read stdin: {}", e)));
+                    }
+                }
+            }
+        }
+    }
+
+    fn byte_ready(&mut self, timeout: Duration) -> Result<bool> {
+        let mut pollfd = libc::pollfd {
+            fd: self.inner.get_stdin().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        let res = unsafe { libc::poll(&mut pollfd, 1, timeout) };
+        if res < 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(Error::IoRead(format!("unable to poll stdin: {}", e)));
+        }
+        Ok(res > 0)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.inner
+            .get_stout()
+            .write_all(data)
+            .map_err(|e| Error::IoWrite(format!("unable to write to stdout: {}", e)))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner
+            .flush()
+            .map_err(|e| Error::IoFlush(format!("unable to flush stdout: {}", e)))
+    }
+
+    fn size(&self) -> Result<(usize, usize)> {
+        let fd = self.inner.get_stout().as_raw_fd();
+        let mut winsize = std::mem::MaybeUninit::<libc::winsize>::uninit();
+        let res = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, winsize.as_mut_ptr()) };
+        if res != 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(Error::Terminal(format!("unable to get window size: {}", e)));
+        }
+        let winsize = unsafe { winsize.assume_init() };
+        Ok((winsize.ws_col as usize, winsize.ws_row as usize))
+    }
+
+    fn enable_raw_mode(&mut self) -> Result<()> {
+        let fd = self.inner.get_stdin().as_raw_fd();
+        let original = get_termios(fd)?;
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+        raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        set_termios(fd, &raw)?;
+        // Keep the mode from before the first call if raw mode is enabled twice.
+        self.saved.get_or_insert(original);
+        Ok(())
+    }
+
+    fn disable_raw_mode(&mut self) -> Result<()> {
+        let fd = self.inner.get_stdin().as_raw_fd();
+        match self.saved.take() {
+            Some(termios) => set_termios(fd, &termios),
+            None => Ok(()),
+        }
+    }
+}
+
+fn get_termios(fd: i32) -> Result<libc::termios> {
+    let mut termios = std::mem::MaybeUninit::uninit();
+    if unsafe { libc::tcgetattr(fd, termios.as_mut_ptr()) } != 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(Error::Terminal(format!(
+            "unable to get terminal mode: {}",
+            e
+        )));
+    }
+    Ok(unsafe { termios.assume_init() })
+}
+
+fn set_termios(fd: i32, termios: &libc::termios) -> Result<()> {
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, termios) } != 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(Error::Terminal(format!(
+            "unable to set terminal mode: {}",
+            e
+        )));
+    }
+    Ok(())
+}
+
+/// In-memory terminal that replays scripted input and records output.
+pub struct MockTerminal {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+    columns: usize,
+    rows: usize,
+    raw_mode: bool,
+    fail_writes: bool,
+}
+
+impl MockTerminal {
+    /// Create an 80x24 mock terminal that will return `input` byte by byte.
+    pub fn new(input: impl AsRef<[u8]>) -> Self {
+        MockTerminal {
+            input: input.as_ref().iter().copied().collect(),
+            output: Vec::new(),
+            columns: 80,
+            rows: 24,
+            raw_mode: false,
+            fail_writes: false,
+        }
+    }
+
+    /// Queue more bytes to be read after the existing input.
+    pub fn push_input(&mut self, input: impl AsRef<[u8]>) {
+        self.input.extend(input.as_ref());
+    }
+
+    /// Everything written to the terminal so far.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Output decoded as UTF-8, with invalid bytes replaced.
+    pub fn output_str(&self) -> String {
+        String::from_utf8_lossy(&self.output).into_owned()
+    }
+
+    /// Discard recorded output.
+    pub fn clear_output(&mut self) {
+        self.output.clear();
+    }
+
+    pub fn set_size(&mut self, columns: usize, rows: usize) {
+        self.columns = columns;
+        self.rows = rows;
+    }
+
+    pub fn is_raw_mode(&self) -> bool {
+        self.raw_mode
+    }
+
+    /// Make every write fail, to exercise error handling.
+    pub fn set_fail_writes(&mut self, fail: bool) {
+        self.fail_writes = fail;
+    }
+}
+
+impl Terminal for MockTerminal {
+    fn read_byte(&mut self) -> Result<u8> {
+        self.input
+            .pop_front()
+            .ok_or_else(|| Error::IoRead(String::from("no scripted input left")))
+    }
+
+    fn byte_ready(&mut self, _timeout: Duration) -> Result<bool> {
+        Ok(!self.input.is_empty())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        if self.fail_writes {
+            return Err(Error::IoWrite(String::from("scripted write failure")));
+        }
+        self.output.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> Result<(usize, usize)> {
+        Ok((self.columns, self.rows))
+    }
+
+    fn enable_raw_mode(&mut self) -> Result<()> {
+        self.raw_mode = true;
+        Ok(())
+    }
+
+    fn disable_raw_mode(&mut self) -> Result<()> {
+        self.raw_mode = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use super::terminal::Terminal;
+use super::{Repl, Result};
+
+/// Line and cursor position at some point during editing.
+type Snapshot = (String, usize);
+
+/// Undo and redo history for the line being edited.
+///
+/// The line is compared before each key, so every edit, including history
+/// recall and completion, becomes one undo step. Consecutive character
+/// inserts are grouped together.
+#[derive(Default)]
+pub(super) struct UndoStack {
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    /// State when the current key started.
+    before: Option<Snapshot>,
+    /// The current key inserted a character.
+    inserted: bool,
+    /// The last undo step was a character insert.
+    grouping: bool,
+}
+
+impl UndoStack {
+    /// Record the step made by the previous key, if it changed the line,
+    /// and remember the state before the next key.
+    pub(super) fn start_key(&mut self, line: &str, cursor: usize) {
+        let inserted = std::mem::take(&mut self.inserted);
+        if let Some(before) = self.before.take()
+            && before.0 != line
+        {
+            if !(inserted && self.grouping) {
+                self.undo.push(before);
+            }
+            self.redo.clear();
+            self.grouping = inserted;
+        } else {
+            self.grouping = false;
+        }
+        self.before = Some((line.to_string(), cursor));
+    }
+
+    /// Note that the current key typed a character.
+    pub(super) fn note_insert(&mut self) {
+        self.inserted = true;
+    }
+
+    pub(super) fn clear(&mut self) {
+        *self = UndoStack::default();
+    }
+}
+
+impl<T: Terminal> Repl<T> {
+    /// Restore the line as it was before the last edit.
+    pub(super) fn undo(&mut self) -> Result<()> {
+        let Some(snapshot) = self.undo_stack.undo.pop() else {
+            return Ok(());
+        };
+        let current = self.restore(snapshot);
+        self.undo_stack.redo.push(current);
+        self.refresh_line()
+    }
+
+    /// Reapply the last edit that was undone.
+    pub(super) fn redo(&mut self) -> Result<()> {
+        let Some(snapshot) = self.undo_stack.redo.pop() else {
+            return Ok(());
+        };
+        let current = self.restore(snapshot);
+        self.undo_stack.undo.push(current);
+        self.refresh_line()
+    }
+
+    /// Replace the line and cursor with `snapshot`, returning the old ones.
+    fn restore(&mut self, (line, cursor): Snapshot) -> Snapshot {
+        let current = (std::mem::replace(&mut self.line, line), self.cursor_pos);
+        self.cursor_pos = cursor.min(self.line.len());
+        // Undoing is not itself an edit to record.
+        self.undo_stack.before = Some((self.line.clone(), self.cursor_pos));
+        self.undo_stack.grouping = false;
+        current
+    }
+}
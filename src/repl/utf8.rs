@@ -0,0 +1,156 @@
+use std::fmt::Display;
+
+/// Bytes that could not be decoded as UTF-8.
+pub struct InvalidSequence {
+    /// Bytes that make up the rejected sequence.
+    pub bytes: Vec<u8>,
+    /// Byte that interrupted the sequence and still needs to be processed.
+    pub unconsumed: Option<u8>,
+}
+
+impl Display for InvalidSequence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid UTF-8 sequence [")?;
+        for (i, b) in self.bytes.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:#04x}", b)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// Incremental UTF-8 decoder fed one byte at a time.
+#[derive(Default)]
+pub struct Utf8Decoder {
+    buf: [u8; 4],
+    len: usize,
+    expected: usize,
+}
+
+impl Utf8Decoder {
+    /// Feed a byte into the decoder. Returns the decoded char once a sequence
+    /// is complete, or `None` while continuation bytes are still expected.
+    pub fn push(&mut self, byte: u8) -> std::result::Result<Option<char>, InvalidSequence> {
+        if self.len == 0 {
+            let expected = match byte {
+                0x00..=0x7f => return Ok(Some(byte as char)),
+                0xc2..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf4 => 4,
+                _ => {
+                    return Err(InvalidSequence {
+                        bytes: vec![byte],
+                        unconsumed: None,
+                    });
+                }
+            };
+            self.buf[0] = byte;
+            self.len = 1;
+            self.expected = expected;
+            return Ok(None);
+        }
+
+        // A byte that is not a continuation byte ends the sequence early. It is
+        // handed back so the caller can treat it as the start of new input.
+        if byte & 0xc0 != 0x80 {
+            let bytes = self.buf[..self.len].to_vec();
+            self.reset();
+            return Err(InvalidSequence {
+                bytes,
+                unconsumed: Some(byte),
+            });
+        }
+
+        self.buf[self.len] = byte;
+        self.len += 1;
+        if self.len < self.expected {
+            return Ok(None);
+        }
+
+        let bytes = &self.buf[..self.len];
+        let decoded = std::str::from_utf8(bytes)
+            .ok()
+            .and_then(|s| s.chars().next());
+        let result = match decoded {
+            Some(c) => Ok(Some(c)),
+            // Overlong encodings and surrogates pass the length checks above.
+            None => Err(InvalidSequence {
+                bytes: bytes.to_vec(),
+                unconsumed: None,
+            }),
+        };
+        self.reset();
+        result
+    }
+
+    /// Discard any partially decoded sequence.
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.expected = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed every byte, collecting decoded chars and the bytes of errors.
+    fn decode(bytes: &[u8]) -> Vec<std::result::Result<char, Vec<u8>>> {
+        let mut decoder = Utf8Decoder::default();
+        let mut decoded = Vec::new();
+        let mut pending: Vec<u8> = bytes.to_vec();
+        pending.reverse();
+        while let Some(byte) = pending.pop() {
+            match decoder.push(byte) {
+                Ok(Some(c)) => decoded.push(Ok(c)),
+                Ok(None) => {}
+                Err(e) => {
+                    decoded.push(Err(e.bytes));
+                    pending.extend(e.unconsumed);
+                }
+            }
+        }
+        decoded
+    }
+
+    #[test]
+    fn decodes_every_sequence_length() {
+        assert_eq!(
+            decode("aé€😀".as_bytes()),
+            [Ok('a'), Ok('é'), Ok('€'), Ok('😀')]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_lead_bytes() {
+        assert_eq!(decode(b"\x80a"), [Err(vec![0x80]), Ok('a')]);
+        assert_eq!(decode(b"\xc0"), [Err(vec![0xc0])]);
+        assert_eq!(decode(b"\xff"), [Err(vec![0xff])]);
+    }
+
+    #[test]
+    fn interrupted_sequence_hands_back_the_byte() {
+        assert_eq!(decode(b"\xe2\x82a"), [Err(vec![0xe2, 0x82]), Ok('a')]);
+        let mut decoder = Utf8Decoder::default();
+        assert!(matches!(decoder.push(0xc3), Ok(None)));
+        let err = decoder.push(b'x').err().map(|e| (e.bytes, e.unconsumed));
+        assert_eq!(err, Some((vec![0xc3], Some(b'x'))));
+    }
+
+    #[test]
+    fn rejects_surrogates_and_overlong_encodings() {
+        assert_eq!(decode(b"\xed\xa0\x80"), [Err(vec![0xed, 0xa0, 0x80])]);
+        assert_eq!(decode(b"\xe0\x80\xaf"), [Err(vec![0xe0, 0x80, 0xaf])]);
+    }
+
+    #[test]
+    fn error_lists_the_bytes() {
+        let err = InvalidSequence {
+            bytes: vec![0xe2, 0x82],
+            unconsumed: None,
+        };
+        assert_eq!(err.to_string(), "invalid UTF-8 sequence [0xe2 0x82]");
+    }
+}
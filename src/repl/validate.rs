@@ -0,0 +1,244 @@
+use std::fmt::Display;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// Result of checking whether a line can be submitted.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Validation {
+    Complete,
+    /// More input is needed to close an open delimiter.
+    Incomplete,
+    /// A closing delimiter does not match what is open.
+    Invalid(String),
+}
+
+/// Something waiting to be closed.
+#[derive(Clone, Copy)]
+enum Open {
+    Bracket(char),
+    Keyword(usize),
+}
+
+/// Checks that brackets, quotes and keyword blocks in a line are balanced.
+///
+/// Tracks `()`, `[]` and `{}` nesting, single, double and backtick quotes
+/// with backslash escapes, and any registered keyword pairs such as
+/// `begin`/`end`. Keywords only match whole words.
+#[derive(Default)]
+pub struct DelimiterValidator {
+    keywords: Vec<(String, String)>,
+}
+
+impl DelimiterValidator {
+    pub fn new() -> Self {
+        DelimiterValidator::default()
+    }
+
+    /// Treat `open` and `close` as a block that must be balanced.
+    pub fn add_keyword_pair(&mut self, open: impl Into<String>, close: impl Into<String>) {
+        self.keywords.push((open.into(), close.into()));
+    }
+
+    /// Returns true unless the line is waiting for a delimiter to be closed.
+    /// Mismatched delimiters count as finished; return `validate` from
+    /// `LineHandler::validate` to have the `Repl` reject them instead.
+    pub fn is_finished(&self, line: &str) -> bool {
+        self.validate(line) != Validation::Incomplete
+    }
+
+    pub fn validate(&self, line: &str) -> Validation {
+        let mut stack: Vec<Open> = Vec::new();
+
+        for token in Tokens::new(line) {
+            match token {
+                Token::UnclosedQuote => return Validation::Incomplete,
+                Token::Open(_, c) => stack.push(Open::Bracket(c)),
+                Token::Close(_, c) => match stack.pop() {
+                    Some(Open::Bracket(open)) if open == opener_of(c) => {}
+                    Some(open) => return self.mismatch(c, open),
+                    None => return Validation::Invalid(format!("unexpected `{}`", c)),
+                },
+                Token::Word(word) => {
+                    if let Some(i) = self.keywords.iter().position(|(open, _)| open == word) {
+                        stack.push(Open::Keyword(i));
+                    } else if self.keywords.iter().any(|(_, close)| close == word) {
+                        match stack.pop() {
+                            Some(Open::Keyword(i)) if self.keywords[i].1 == word => {}
+                            Some(open) => return self.mismatch(word, open),
+                            None => return Validation::Invalid(format!("unexpected `{}`", word)),
+                        }
+                    }
+                }
+            }
+        }
+
+        if stack.is_empty() {
+            Validation::Complete
+        } else {
+            Validation::Incomplete
+        }
+    }
+
+    fn mismatch(&self, found: impl Display, open: Open) -> Validation {
+        let expected = match open {
+            Open::Bracket('(') => String::from(")"),
+            Open::Bracket('[') => String::from("]"),
+            Open::Bracket(_) => String::from("}"),
+            Open::Keyword(i) => self.keywords[i].1.clone(),
+        };
+        Validation::Invalid(format!("expected `{}` but found `{}`", expected, found))
+    }
+}
+
+/// Part of a line that delimiter matching looks at. Quoted text, including
+/// backslash escapes inside it, is skipped.
+pub(super) enum Token<'a> {
+    /// An opening bracket and its byte offset.
+    Open(usize, char),
+    /// A closing bracket and its byte offset.
+    Close(usize, char),
+    Word(&'a str),
+    /// A quote that is still open at the end of the line.
+    UnclosedQuote,
+}
+
+/// Splits a line into `Token`s, shared by validation and bracket
+/// highlighting so both agree on what is quoted.
+pub(super) struct Tokens<'a> {
+    line: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Tokens<'a> {
+    pub(super) fn new(line: &'a str) -> Self {
+        Tokens {
+            line,
+            chars: line.char_indices().peekable(),
+        }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        while let Some((idx, c)) = self.chars.next() {
+            match c {
+                '\'' | '"' | '`' => {
+                    let mut closed = false;
+                    while let Some((_, q)) = self.chars.next() {
+                        if q == '\\' {
+                            self.chars.next();
+                        } else if q == c {
+                            closed = true;
+                            break;
+                        }
+                    }
+                    if !closed {
+                        return Some(Token::UnclosedQuote);
+                    }
+                }
+                '(' | '[' | '{' => return Some(Token::Open(idx, c)),
+                ')' | ']' | '}' => return Some(Token::Close(idx, c)),
+                c if is_word_char(c) => {
+                    let mut end = idx + c.len_utf8();
+                    while let Some(&(next_idx, next)) = self.chars.peek() {
+                        if !is_word_char(next) {
+                            break;
+                        }
+                        end = next_idx + next.len_utf8();
+                        self.chars.next();
+                    }
+                    return Some(Token::Word(&self.line[idx..end]));
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// Opening bracket that `close` pairs with.
+pub(super) fn opener_of(close: char) -> char {
+    match close {
+        ')' => '(',
+        ']' => '[',
+        _ => '{',
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Default delimiter check, usable directly as the Repl's `line_is_finished`.
+/// Capture a configured `DelimiterValidator` in a closure to check keywords.
+pub fn delimiters_balanced(line: String) -> bool {
+    DelimiterValidator::default().is_finished(&line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(message: &str) -> Validation {
+        Validation::Invalid(String::from(message))
+    }
+
+    #[test]
+    fn balanced_brackets_are_complete() {
+        let validator = DelimiterValidator::new();
+        assert_eq!(validator.validate(""), Validation::Complete);
+        assert_eq!(validator.validate("f(a[0], {b})"), Validation::Complete);
+    }
+
+    #[test]
+    fn open_brackets_are_incomplete() {
+        let validator = DelimiterValidator::new();
+        assert_eq!(validator.validate("f(a[0]"), Validation::Incomplete);
+        assert_eq!(validator.validate("{\n(\n)"), Validation::Incomplete);
+    }
+
+    #[test]
+    fn mismatched_brackets_are_invalid() {
+        let validator = DelimiterValidator::new();
+        assert_eq!(
+            validator.validate("(]"),
+            invalid("expected `)` but found `]`")
+        );
+        assert_eq!(validator.validate("a)"), invalid("unexpected `)`"));
+    }
+
+    #[test]
+    fn quotes_hide_brackets() {
+        let validator = DelimiterValidator::new();
+        assert_eq!(validator.validate("\"(\""), Validation::Complete);
+        assert_eq!(validator.validate("'a\\'('"), Validation::Complete);
+        assert_eq!(validator.validate("`)`"), Validation::Complete);
+        assert_eq!(validator.validate("\"abc"), Validation::Incomplete);
+        assert_eq!(validator.validate("'a\\'"), Validation::Incomplete);
+    }
+
+    #[test]
+    fn keyword_pairs_match_whole_words() {
+        let mut validator = DelimiterValidator::new();
+        validator.add_keyword_pair("begin", "end");
+        assert_eq!(validator.validate("begin x end"), Validation::Complete);
+        assert_eq!(validator.validate("begin x"), Validation::Incomplete);
+        assert_eq!(validator.validate("beginning ending"), Validation::Complete);
+        assert_eq!(validator.validate("end"), invalid("unexpected `end`"));
+        assert_eq!(
+            validator.validate("begin (end)"),
+            invalid("expected `)` but found `end`")
+        );
+        assert_eq!(validator.validate("\"begin\""), Validation::Complete);
+    }
+
+    #[test]
+    fn is_finished_accepts_invalid_lines() {
+        let validator = DelimiterValidator::new();
+        assert!(validator.is_finished("(]"));
+        assert!(!validator.is_finished("("));
+        assert!(delimiters_balanced(String::from("()")));
+    }
+}
@@ -0,0 +1,709 @@
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::keys::{KeyCode, KeyEvent, Modifiers};
+use super::terminal::Terminal;
+use super::{Repl, Result};
+
+/// Largest count a command accepts. Larger counts are clamped so a mistyped
+/// count cannot overflow or make a command run for ages.
+const MAX_COUNT: usize = 9999;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ViMode {
+    Insert,
+    Normal,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Clone, Copy)]
+enum Motion {
+    Left,
+    Right,
+    WordForward { big: bool },
+    WordBackward { big: bool },
+    WordEnd { big: bool },
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    Find(Find),
+    RepeatFind { reverse: bool },
+}
+
+#[derive(Clone, Copy)]
+struct Find {
+    target: char,
+    forward: bool,
+    /// Stop one grapheme before the target, as `t` and `T` do.
+    till: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Command {
+    Move(Motion),
+    /// Apply an operator to a motion, or to the whole row when doubled.
+    Operate(Operator, Option<Motion>),
+    DeleteChar,
+    DeleteCharBefore,
+    Replace(char),
+    PasteAfter,
+    PastePrevious,
+    Undo,
+    RepeatChange,
+    Insert,
+    Append,
+    InsertAtStart,
+    AppendAtEnd,
+    Up,
+    Down,
+}
+
+enum Parse {
+    Incomplete,
+    Invalid,
+    Complete(usize, Command),
+}
+
+/// Change recorded for `.` to repeat.
+struct Change {
+    keys: String,
+    inserted: String,
+}
+
+/// State of vi editing mode.
+pub(super) struct ViState {
+    pub(super) mode: ViMode,
+    /// Normal mode keys typed so far for an unfinished command.
+    pending: String,
+    last_find: Option<Find>,
+    last_change: Option<Change>,
+    /// True while text typed in insert mode belongs to `last_change`.
+    recording: bool,
+}
+
+impl Default for ViState {
+    fn default() -> Self {
+        ViState {
+            mode: ViMode::Insert,
+            pending: String::new(),
+            last_find: None,
+            last_change: None,
+            recording: false,
+        }
+    }
+}
+
+impl ViState {
+    /// Note a character typed in insert mode so `.` can replay it.
+    pub(super) fn record_insert(&mut self, ch: char) {
+        if self.recording
+            && let Some(change) = self.last_change.as_mut()
+        {
+            change.inserted.push(ch);
+        }
+    }
+}
+
+/// Parse normal mode keys: `[count] command`, where operators take their own
+/// `[count] motion` or are doubled to act on the whole row.
+fn parse(keys: &str) -> Parse {
+    let mut chars = keys.chars().peekable();
+    let count = parse_count(&mut chars);
+    let Some(c) = chars.next() else {
+        return Parse::Incomplete;
+    };
+
+    let operator = match c {
+        'd' => Some(Operator::Delete),
+        'c' => Some(Operator::Change),
+        'y' => Some(Operator::Yank),
+        _ => None,
+    };
+    if let Some(operator) = operator {
+        let motion_count = parse_count(&mut chars);
+        let Some(m) = chars.next() else {
+            return Parse::Incomplete;
+        };
+        if m == c {
+            return Parse::Complete(
+                (count * motion_count).min(MAX_COUNT),
+                Command::Operate(operator, None),
+            );
+        }
+        return match parse_motion(m, chars.next()) {
+            Parse::Complete(_, Command::Move(motion)) => Parse::Complete(
+                (count * motion_count).min(MAX_COUNT),
+                Command::Operate(operator, Some(motion)),
+            ),
+            Parse::Incomplete => Parse::Incomplete,
+            _ => Parse::Invalid,
+        };
+    }
+
+    let command = match c {
+        'x' => Command::DeleteChar,
+        'X' => Command::DeleteCharBefore,
+        'D' => Command::Operate(Operator::Delete, Some(Motion::LineEnd)),
+        'C' => Command::Operate(Operator::Change, Some(Motion::LineEnd)),
+        'Y' => Command::Operate(Operator::Yank, None),
+        'p' => Command::PasteAfter,
+        'P' => Command::PastePrevious,
+        'u' => Command::Undo,
+        '.' => Command::RepeatChange,
+        'i' => Command::Insert,
+        'a' => Command::Append,
+        'I' => Command::InsertAtStart,
+        'A' => Command::AppendAtEnd,
+        'k' => Command::Up,
+        'j' => Command::Down,
+        'r' => match chars.next() {
+            Some(target) => Command::Replace(target),
+            None => return Parse::Incomplete,
+        },
+        _ => {
+            return match parse_motion(c, chars.next()) {
+                Parse::Complete(_, command) => Parse::Complete(count, command),
+                other => other,
+            };
+        }
+    };
+    Parse::Complete(count, command)
+}
+
+fn parse_count(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> usize {
+    let mut count = 0;
+    while let Some(&c) = chars.peek() {
+        // A leading zero is the line start motion, not a count.
+        match c.to_digit(10) {
+            Some(d) if d > 0 || count > 0 => {
+                count = (count * 10 + d as usize).min(MAX_COUNT);
+                chars.next();
+            }
+            _ => break,
+        }
+    }
+    count.max(1)
+}
+
+fn parse_motion(c: char, next: Option<char>) -> Parse {
+    let motion = match c {
+        'h' => Motion::Left,
+        'l' | ' ' => Motion::Right,
+        'w' => Motion::WordForward { big: false },
+        'W' => Motion::WordForward { big: true },
+        'b' => Motion::WordBackward { big: false },
+        'B' => Motion::WordBackward { big: true },
+        'e' => Motion::WordEnd { big: false },
+        'E' => Motion::WordEnd { big: true },
+        '0' => Motion::LineStart,
+        '^' => Motion::FirstNonBlank,
+        '$' => Motion::LineEnd,
+        ';' => Motion::RepeatFind { reverse: false },
+        ',' => Motion::RepeatFind { reverse: true },
+        'f' | 'F' | 't' | 'T' => match next {
+            Some(target) => Motion::Find(Find {
+                target,
+                forward: c == 'f' || c == 't',
+                till: c == 't' || c == 'T',
+            }),
+            None => return Parse::Incomplete,
+        },
+        _ => return Parse::Invalid,
+    };
+    Parse::Complete(1, Command::Move(motion))
+}
+
+#[derive(PartialEq, Eq)]
+enum CharClass {
+    Blank,
+    Word,
+    Punctuation,
+}
+
+fn class_of(grapheme: &str, big: bool) -> CharClass {
+    let c = grapheme.chars().next().unwrap_or(' ');
+    if c.is_whitespace() {
+        CharClass::Blank
+    } else if big || c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+impl<T: Terminal> Repl<T> {
+    pub(super) fn vi_normal_mode(&self) -> bool {
+        self.vi.as_ref().is_some_and(|vi| vi.mode == ViMode::Normal)
+    }
+
+    /// Leave insert mode. As in vi, the cursor steps back onto the last
+    /// character that was typed.
+    pub(super) fn enter_vi_normal_mode(&mut self) -> Result<()> {
+        let Some(vi) = self.vi.as_mut() else {
+            return Ok(());
+        };
+        vi.pending.clear();
+        if vi.mode == ViMode::Normal {
+            return Ok(());
+        }
+        vi.mode = ViMode::Normal;
+        vi.recording = false;
+        if self.cursor_pos > self.row_start(self.cursor_pos)
+            && let Some(prev) = self.prev_grapheme_boundary()
+        {
+            self.cursor_pos = prev;
+        }
+        self.refresh_line()
+    }
+
+    /// Handle a key pressed in normal mode. Other keys keep their insert
+    /// mode meaning so Enter, Ctrl-C, the arrows and the like behave the same.
+    pub(super) fn handle_vi_key(&mut self, key: KeyEvent) -> Result<()> {
+        match (key.code, key.modifiers) {
+            (KeyCode::Backspace, Modifiers::NONE) => self.vi_key('h')?,
+            (KeyCode::Char(ch), Modifiers::NONE) => self.vi_key(ch)?,
+            _ => {
+                if let Some(vi) = self.vi.as_mut() {
+                    vi.pending.clear();
+                }
+                return self.handle_mapped_key(key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a key to the pending normal mode command and run it once complete.
+    pub(super) fn vi_key(&mut self, key: char) -> Result<()> {
+        let Some(vi) = self.vi.as_mut() else {
+            return Ok(());
+        };
+        vi.pending.push(key);
+        let keys = vi.pending.clone();
+        match parse(&keys) {
+            Parse::Incomplete => Ok(()),
+            Parse::Invalid => {
+                vi.pending.clear();
+                Ok(())
+            }
+            Parse::Complete(count, command) => {
+                vi.pending.clear();
+                self.run_vi_command(&keys, count, command)
+            }
+        }
+    }
+
+    fn run_vi_command(&mut self, keys: &str, count: usize, command: Command) -> Result<()> {
+        let changes = !matches!(
+            command,
+            Command::Move(_)
+                | Command::Operate(Operator::Yank, _)
+                | Command::Undo
+                | Command::RepeatChange
+                | Command::Up
+                | Command::Down
+        );
+        if changes && let Some(vi) = self.vi.as_mut() {
+            vi.last_change = Some(Change {
+                keys: keys.to_string(),
+                inserted: String::new(),
+            });
+        }
+
+        match command {
+            Command::Move(motion) => {
+                if let Some(target) = self.motion_target(motion, count) {
+                    self.cursor_pos = target;
+                }
+            }
+            Command::Operate(operator, motion) => self.vi_operate(operator, motion, count)?,
+            Command::DeleteChar => {
+                let end = self.advance_graphemes(self.cursor_pos, count, true);
+                self.vi_cut(self.cursor_pos..end, false);
+            }
+            Command::DeleteCharBefore => {
+                let start = self.retreat_graphemes(self.cursor_pos, count);
+                self.vi_cut(start..self.cursor_pos, false);
+            }
+            Command::Replace(target) => {
+                let end = self.advance_graphemes(self.cursor_pos, count, true);
+                let graphemes = self.line[self.cursor_pos..end].graphemes(true).count();
+                if graphemes == count {
+                    let replacement = target.to_string().repeat(count);
+                    self.line.replace_range(self.cursor_pos..end, &replacement);
+                    self.cursor_pos += replacement.len() - target.len_utf8();
+                }
+            }
+            Command::PasteAfter | Command::PastePrevious => {
+                if let Some(text) = self.kill_ring.latest() {
+                    let text = text.repeat(count);
+                    if matches!(command, Command::PasteAfter)
+                        && let Some(next) = self.next_grapheme_boundary()
+                        && &self.line[self.cursor_pos..next] != "\n"
+                    {
+                        self.cursor_pos = next;
+                    }
+                    self.line.insert_str(self.cursor_pos, &text);
+                    self.cursor_pos += text.len();
+                    if let Some(prev) = self.prev_grapheme_boundary() {
+                        self.cursor_pos = prev;
+                    }
+                }
+            }
+            Command::Undo => self.undo()?,
+            Command::RepeatChange => return self.repeat_vi_change(),
+            Command::Insert => self.enter_vi_insert_mode(),
+            Command::Append => {
+                if let Some(next) = self.next_grapheme_boundary()
+                    && &self.line[self.cursor_pos..next] != "\n"
+                {
+                    self.cursor_pos = next;
+                }
+                self.enter_vi_insert_mode();
+            }
+            Command::InsertAtStart => {
+                if let Some(target) = self.motion_target(Motion::FirstNonBlank, 1) {
+                    self.cursor_pos = target;
+                }
+                self.enter_vi_insert_mode();
+            }
+            Command::AppendAtEnd => {
+                self.cursor_pos = self.row_end(self.cursor_pos);
+                self.enter_vi_insert_mode();
+            }
+            Command::Up | Command::Down => {
+                for _ in 0..count {
+                    let before = (self.lines_pos, self.cursor_pos);
+                    if matches!(command, Command::Up) {
+                        self.move_up()?;
+                    } else {
+                        self.move_down()?;
+                    }
+                    // Stop once there is nothing further to move to.
+                    if (self.lines_pos, self.cursor_pos) == before {
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.clamp_vi_cursor();
+        self.refresh_line()
+    }
+
+    fn vi_operate(
+        &mut self,
+        operator: Operator,
+        motion: Option<Motion>,
+        count: usize,
+    ) -> Result<()> {
+        let range = match motion {
+            // A doubled operator acts on whole rows.
+            None => {
+                let start = self.row_start(self.cursor_pos);
+                let mut end = self.row_end(self.cursor_pos);
+                for _ in 1..count {
+                    if end < self.line.len() {
+                        end = self.row_end(end + 1);
+                    }
+                }
+                start..end
+            }
+            Some(motion) => {
+                // `cw` on a word changes to the end of it, like `ce`.
+                let motion = match (operator, motion) {
+                    (Operator::Change, Motion::WordForward { big })
+                        if !self.line[self.cursor_pos..]
+                            .starts_with(|c: char| c.is_whitespace()) =>
+                    {
+                        Motion::WordEnd { big }
+                    }
+                    _ => motion,
+                };
+                let Some(target) = self.motion_target(motion, count) else {
+                    return Ok(());
+                };
+                let inclusive = matches!(
+                    motion,
+                    Motion::WordEnd { .. } | Motion::Find(_) | Motion::RepeatFind { .. }
+                ) && target >= self.cursor_pos;
+                if inclusive {
+                    self.cursor_pos..self.advance_graphemes(target, 1, true)
+                } else if target < self.cursor_pos {
+                    target..self.cursor_pos
+                } else {
+                    self.cursor_pos..target
+                }
+            }
+        };
+
+        match operator {
+            Operator::Delete => self.vi_cut(range, false),
+            Operator::Change => {
+                self.vi_cut(range, false);
+                self.enter_vi_insert_mode();
+            }
+            Operator::Yank => self.vi_cut(range, true),
+        }
+        Ok(())
+    }
+
+    /// Copy `range` into the kill ring, removing it unless only yanking.
+    fn vi_cut(&mut self, range: Range<usize>, yank_only: bool) {
+        if range.is_empty() {
+            return;
+        }
+        self.kill_ring.push(&self.line[range.clone()]);
+        if !yank_only {
+            self.line.replace_range(range.clone(), "");
+        }
+        self.cursor_pos = range.start;
+    }
+
+    fn enter_vi_insert_mode(&mut self) {
+        // As in vi, text typed next is undone together with this command.
+        self.undo_stack.note_insert();
+        if let Some(vi) = self.vi.as_mut() {
+            vi.mode = ViMode::Insert;
+            vi.recording = vi.last_change.is_some();
+        }
+    }
+
+    /// Run the last change again, retyping any text it inserted.
+    fn repeat_vi_change(&mut self) -> Result<()> {
+        let Some(vi) = self.vi.as_mut() else {
+            return Ok(());
+        };
+        let Some(change) = vi.last_change.take() else {
+            return Ok(());
+        };
+        if let Parse::Complete(count, command) = parse(&change.keys) {
+            self.run_vi_command(&change.keys, count, command)?;
+            if !self.vi_normal_mode() {
+                for ch in change.inserted.chars() {
+                    self.insert_char(ch)?;
+                }
+                self.enter_vi_normal_mode()?;
+            }
+        }
+        if let Some(vi) = self.vi.as_mut() {
+            vi.last_change = Some(change);
+        }
+        self.refresh_line()
+    }
+
+    /// In normal mode the cursor rests on a character, never past the end of
+    /// a non-empty row.
+    fn clamp_vi_cursor(&mut self) {
+        if self.vi_normal_mode()
+            && self.cursor_pos == self.row_end(self.cursor_pos)
+            && self.cursor_pos > self.row_start(self.cursor_pos)
+            && let Some(prev) = self.prev_grapheme_boundary()
+        {
+            self.cursor_pos = prev;
+        }
+    }
+
+    /// Byte offset `count` graphemes after `pos`, stopping at the end of the
+    /// row when `in_row` is set.
+    fn advance_graphemes(&self, pos: usize, count: usize, in_row: bool) -> usize {
+        let limit = if in_row {
+            self.row_end(pos)
+        } else {
+            self.line.len()
+        };
+        self.line[pos..limit]
+            .grapheme_indices(true)
+            .nth(count)
+            .map_or(limit, |(idx, _)| pos + idx)
+    }
+
+    /// Byte offset `count` graphemes before `pos`, stopping at the row start.
+    fn retreat_graphemes(&self, pos: usize, count: usize) -> usize {
+        let start = self.row_start(pos);
+        self.line[start..pos]
+            .grapheme_indices(true)
+            .rev()
+            .nth(count - 1)
+            .map_or(start, |(idx, _)| start + idx)
+    }
+
+    /// Where `motion` repeated `count` times takes the cursor.
+    fn motion_target(&mut self, motion: Motion, count: usize) -> Option<usize> {
+        let pos = self.cursor_pos;
+        let graphemes: Vec<(usize, &str)> = self.line.grapheme_indices(true).collect();
+        let index = graphemes
+            .iter()
+            .position(|&(idx, _)| idx >= pos)
+            .unwrap_or(graphemes.len());
+        let offset = |i: usize| graphemes.get(i).map_or(self.line.len(), |&(idx, _)| idx);
+
+        let target = match motion {
+            Motion::Left => self.retreat_graphemes(pos, count),
+            Motion::Right => self.advance_graphemes(pos, count, true),
+            Motion::LineStart => self.row_start(pos),
+            Motion::LineEnd => self.row_end(pos),
+            Motion::FirstNonBlank => {
+                let start = self.row_start(pos);
+                let end = self.row_end(pos);
+                self.line[start..end]
+                    .find(|c: char| !c.is_whitespace())
+                    .map_or(end, |idx| start + idx)
+            }
+            Motion::WordForward { big } => {
+                let mut i = index;
+                for _ in 0..count {
+                    if i >= graphemes.len() {
+                        break;
+                    }
+                    let class = class_of(graphemes[i].1, big);
+                    while i < graphemes.len() && class_of(graphemes[i].1, big) == class {
+                        i += 1;
+                    }
+                    while i < graphemes.len() && class_of(graphemes[i].1, big) == CharClass::Blank {
+                        i += 1;
+                    }
+                }
+                offset(i)
+            }
+            Motion::WordBackward { big } => {
+                let mut i = index;
+                for _ in 0..count {
+                    while i > 0 && class_of(graphemes[i - 1].1, big) == CharClass::Blank {
+                        i -= 1;
+                    }
+                    if i == 0 {
+                        break;
+                    }
+                    let class = class_of(graphemes[i - 1].1, big);
+                    while i > 0 && class_of(graphemes[i - 1].1, big) == class {
+                        i -= 1;
+                    }
+                }
+                offset(i)
+            }
+            Motion::WordEnd { big } => {
+                let mut i = index;
+                for _ in 0..count {
+                    i += 1;
+                    while i < graphemes.len() && class_of(graphemes[i].1, big) == CharClass::Blank {
+                        i += 1;
+                    }
+                    if i >= graphemes.len() {
+                        break;
+                    }
+                    let class = class_of(graphemes[i].1, big);
+                    while i + 1 < graphemes.len() && class_of(graphemes[i + 1].1, big) == class {
+                        i += 1;
+                    }
+                }
+                offset(i.min(graphemes.len().saturating_sub(1)))
+            }
+            Motion::Find(find) => {
+                if let Some(vi) = self.vi.as_mut() {
+                    vi.last_find = Some(find);
+                }
+                return self.find_target(find, count);
+            }
+            Motion::RepeatFind { reverse } => {
+                let mut find = self.vi.as_ref()?.last_find?;
+                if reverse {
+                    find.forward = !find.forward;
+                }
+                return self.find_target(find, count);
+            }
+        };
+        Some(target)
+    }
+
+    /// Position of the `count`th occurrence of a character in the current row.
+    fn find_target(&self, find: Find, count: usize) -> Option<usize> {
+        let start = self.row_start(self.cursor_pos);
+        let end = self.row_end(self.cursor_pos);
+        let needle = find.target.to_string();
+        let row: Vec<(usize, &str)> = self.line[start..end]
+            .grapheme_indices(true)
+            .map(|(idx, g)| (start + idx, g))
+            .collect();
+        let current = row
+            .iter()
+            .position(|&(idx, _)| idx >= self.cursor_pos)
+            .unwrap_or(row.len());
+
+        let mut found = if find.forward {
+            (current + 1..row.len())
+                .filter(|&i| row[i].1 == needle)
+                .nth(count - 1)?
+        } else {
+            (0..current)
+                .rev()
+                .filter(|&i| row[i].1 == needle)
+                .nth(count - 1)?
+        };
+        if find.till {
+            found = if find.forward { found - 1 } else { found + 1 };
+        }
+        Some(row.get(found).map_or(end, |&(idx, _)| idx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::EditMode;
+    use super::super::tests::{repl, repl_with_history, type_keys};
+    use super::*;
+
+    /// Type `input` in vi mode, starting in insert mode.
+    fn run_vi(input: &str) -> String {
+        let mut repl = repl();
+        repl.set_edit_mode(EditMode::Vi);
+        type_keys(&mut repl, input);
+        repl.line().to_string()
+    }
+
+    #[test]
+    fn operators_and_motions() {
+        assert_eq!(run_vi("foo bar baz\x1b0dw"), "bar baz");
+        assert_eq!(run_vi("foo bar baz\x1b0cwx\x1b"), "x bar baz");
+        assert_eq!(run_vi("foo bar baz\x1b0d2w"), "baz");
+        assert_eq!(run_vi("foo bar\x1b0dd"), "");
+        assert_eq!(run_vi("a,b,c\x1b0dt,"), ",b,c");
+    }
+
+    #[test]
+    fn repeat_and_undo() {
+        assert_eq!(run_vi("abcd\x1b0x."), "cd");
+        assert_eq!(run_vi("abcd\x1b0xu"), "abcd");
+        assert_eq!(run_vi("abcd\x1b0xxuu"), "abcd");
+        assert_eq!(run_vi("foo bar\x1b0cwxyz\x1bu"), "foo bar");
+    }
+
+    #[test]
+    fn vi_and_emacs_undo_share_history() {
+        assert_eq!(run_vi("abcd\x1b0x\x1f"), "abcd");
+        assert_eq!(run_vi("ab\x17\x1bu"), "ab");
+        assert_eq!(run_vi("abcd\x1b0xu\x1b/"), "bcd");
+    }
+
+    #[test]
+    fn large_counts_are_clamped() {
+        assert_eq!(run_vi("abc\x1b099999999999999999999x"), "");
+        assert_eq!(run_vi("abc\x1b0d99999999999999999999w"), "");
+        let line = run_vi("abc\x1bx99999999999p");
+        assert_eq!(line.len(), "ab".len() + MAX_COUNT);
+    }
+
+    #[test]
+    fn large_counts_stop_at_the_oldest_history_entry() {
+        let mut repl = repl_with_history(&["one", "two"]);
+        repl.set_edit_mode(EditMode::Vi);
+        type_keys(&mut repl, "\x1b999999999k");
+        assert_eq!(repl.line(), "one");
+    }
+}